@@ -7,7 +7,8 @@ dfx start --background --clean
 dfx identity new --disable-encryption owner || true
 dfx identity new --disable-encryption validator1 || true
 dfx identity new --disable-encryption validator2 || true
-dfx --identity owner deploy
+dfx --identity owner deploy cross_chain --argument '("DFINITY")'
+dfx --identity owner deploy greeting_assets
 CROSSCHAINID=$(dfx canister id cross_chain)
 GREETING=$(dfx canister id greeting)
 
@@ -17,13 +18,15 @@ echo '(*) Greeting register destination contract'
 dfx --identity owner canister call greeting registerDstContract '("NEAR", "receiveGreeting", "9f9350eb575cae7aac7f85a8c62b08d94dcac70a84e3c765464ff87c669fa4e5", "receiveGreeting")'
 echo '(*) Greeting register permitted contract'
 dfx --identity owner canister call greeting registerPermittedContract '("NEAR", "9f9350eb575cae7aac7f85a8c62b08d94dcac70a84e3c765464ff87c669fa4e5", "receiveGreeting")'
+echo '(*) Register source chain in cross chain canister'
+dfx --identity owner canister call cross_chain registerChain '("NEAR", record { family = variant { Near }; address_format = variant { NearAccount }; enabled = true; quorum = null; encoding = variant { Json }; ordered_delivery = false })'
 echo '(*) Validator register in cross chain canister'
 VALIDATOR1=$(dfx --identity validator1 identity get-principal)
-dfx --identity owner canister call cross_chain registerValidator "(principal \"$VALIDATOR1\")"
+dfx --identity owner canister call cross_chain registerValidator "(\"NEAR\", principal \"$VALIDATOR1\")"
 VALIDATOR2=$(dfx --identity validator2 identity get-principal)
-dfx --identity owner canister call cross_chain registerValidator "(principal \"$VALIDATOR2\")"
+dfx --identity owner canister call cross_chain registerValidator "(\"NEAR\", principal \"$VALIDATOR2\")"
 echo '(*) Validators'
-dfx canister call cross_chain getValidators
+dfx canister call cross_chain getValidators '("NEAR")'
 echo '(*) Register locker contract'
 dfx --identity owner canister call cross_chain registerLocker "(principal \"$GREETING\")"
 echo '(*) Send greeting to other chain)'
@@ -0,0 +1,636 @@
+// Solidity contract ABI encoding for payloads exchanged with EVM chains.
+// https://docs.soliditylang.org/en/latest/abi-spec.html
+
+use crate::args;
+use candid::{
+    parser::value::{IDLField, IDLValue},
+    types::Type,
+    CandidType, Deserialize, Int, Nat, TypeEnv,
+};
+use num_bigint::{BigInt, BigUint, Sign};
+use std::convert::TryInto;
+
+const WORD: usize = 32;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum AbiValue {
+    Uint(Nat),
+    Int(Int),
+    // 0x prefixed hex
+    Address(String),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    // bytes1 to bytes32
+    FixedBytes(Vec<u8>),
+    String(String),
+    Array(Vec<AbiValue>),
+    FixedArray(Vec<AbiValue>),
+    Tuple(Vec<AbiValue>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AbiType {
+    Uint(usize),
+    Int(usize),
+    Address,
+    Bool,
+    Bytes,
+    FixedBytes(usize),
+    String,
+    Array(Box<AbiType>),
+    FixedArray(Box<AbiType>, usize),
+    Tuple(Vec<AbiType>),
+}
+
+impl AbiValue {
+    fn is_dynamic(&self) -> bool {
+        match self {
+            AbiValue::Bytes(_) | AbiValue::String(_) | AbiValue::Array(_) => true,
+            AbiValue::FixedArray(values) | AbiValue::Tuple(values) => {
+                values.iter().any(AbiValue::is_dynamic)
+            }
+            _ => false,
+        }
+    }
+
+    // bytes taken in the head of the enclosing tuple
+    fn head_size(&self) -> usize {
+        match self {
+            AbiValue::FixedArray(values) | AbiValue::Tuple(values) if !self.is_dynamic() => {
+                values.iter().map(AbiValue::head_size).sum()
+            }
+            _ => WORD,
+        }
+    }
+}
+
+impl AbiType {
+    fn is_dynamic(&self) -> bool {
+        match self {
+            AbiType::Bytes | AbiType::String | AbiType::Array(_) => true,
+            AbiType::FixedArray(inner, _) => inner.is_dynamic(),
+            AbiType::Tuple(types) => types.iter().any(AbiType::is_dynamic),
+            _ => false,
+        }
+    }
+
+    fn head_size(&self) -> usize {
+        match self {
+            _ if self.is_dynamic() => WORD,
+            AbiType::FixedArray(inner, len) => inner.head_size() * len,
+            AbiType::Tuple(types) => types.iter().map(AbiType::head_size).sum(),
+            _ => WORD,
+        }
+    }
+}
+
+// Encodes `values` as the arguments of a function call, without the selector.
+pub fn encode(values: &[AbiValue]) -> Result<Vec<u8>, String> {
+    encode_tuple(values)
+}
+
+fn encode_tuple(values: &[AbiValue]) -> Result<Vec<u8>, String> {
+    let head_size: usize = values.iter().map(AbiValue::head_size).sum();
+    let mut head = Vec::with_capacity(head_size);
+    let mut tail = vec![];
+    for value in values {
+        if value.is_dynamic() {
+            head.extend(uint_word(head_size + tail.len()));
+            tail.extend(encode_value(value)?);
+        } else {
+            head.extend(encode_value(value)?);
+        }
+    }
+    head.extend(tail);
+    Ok(head)
+}
+
+fn encode_value(value: &AbiValue) -> Result<Vec<u8>, String> {
+    match value {
+        AbiValue::Uint(Nat(value)) => {
+            let bytes = value.to_bytes_be();
+            if bytes.len() > WORD {
+                return Err("uint out of range".to_string());
+            }
+            Ok(left_pad(&bytes, 0))
+        }
+        AbiValue::Int(Int(value)) => {
+            let bytes = value.to_signed_bytes_be();
+            if bytes.len() > WORD {
+                return Err("int out of range".to_string());
+            }
+            let fill = if value.sign() == Sign::Minus { 0xff } else { 0 };
+            Ok(left_pad(&bytes, fill))
+        }
+        AbiValue::Address(address) => {
+            let bytes =
+                hex::decode(address.trim_start_matches("0x")).map_err(|err| format!("{}", err))?;
+            if bytes.len() != 20 {
+                return Err(format!("invalid address {}", address));
+            }
+            Ok(left_pad(&bytes, 0))
+        }
+        AbiValue::Bool(value) => Ok(uint_word(*value as usize)),
+        AbiValue::FixedBytes(bytes) => {
+            if bytes.is_empty() || bytes.len() > WORD {
+                return Err("fixed bytes must hold 1 to 32 bytes".to_string());
+            }
+            Ok(right_pad(bytes))
+        }
+        AbiValue::Bytes(bytes) => {
+            let mut encoded = uint_word(bytes.len());
+            encoded.extend(right_pad(bytes));
+            Ok(encoded)
+        }
+        AbiValue::String(value) => encode_value(&AbiValue::Bytes(value.as_bytes().to_vec())),
+        AbiValue::Array(values) => {
+            let mut encoded = uint_word(values.len());
+            encoded.extend(encode_tuple(values)?);
+            Ok(encoded)
+        }
+        AbiValue::FixedArray(values) | AbiValue::Tuple(values) => encode_tuple(values),
+    }
+}
+
+// Decodes function call arguments of the given types, without the selector.
+pub fn decode(types: &[AbiType], data: &[u8]) -> Result<Vec<AbiValue>, String> {
+    decode_tuple(types, data, 0)
+}
+
+fn decode_tuple(types: &[AbiType], data: &[u8], base: usize) -> Result<Vec<AbiValue>, String> {
+    let mut offset = base;
+    let mut values = Vec::with_capacity(types.len());
+    for ty in types {
+        let value = if ty.is_dynamic() {
+            let pointer = read_usize(data, offset)?;
+            decode_value(
+                ty,
+                data,
+                base.checked_add(pointer).ok_or("offset overflow")?,
+            )?
+        } else {
+            decode_value(ty, data, offset)?
+        };
+        values.push(value);
+        offset += ty.head_size();
+    }
+    Ok(values)
+}
+
+fn decode_value(ty: &AbiType, data: &[u8], at: usize) -> Result<AbiValue, String> {
+    match ty {
+        AbiType::Uint(bits) => {
+            let value = BigUint::from_bytes_be(read_word(data, at)?);
+            if value.bits() > *bits as u64 {
+                return Err(format!("value does not fit uint{}", bits));
+            }
+            Ok(AbiValue::Uint(Nat(value)))
+        }
+        AbiType::Int(bits) => {
+            let value = BigInt::from_signed_bytes_be(read_word(data, at)?);
+            if value.bits() >= *bits as u64 && value != -(BigInt::from(1) << (*bits - 1)) {
+                return Err(format!("value does not fit int{}", bits));
+            }
+            Ok(AbiValue::Int(Int(value)))
+        }
+        AbiType::Address => Ok(AbiValue::Address(format!(
+            "0x{}",
+            hex::encode(&read_word(data, at)?[WORD - 20..])
+        ))),
+        AbiType::Bool => match read_usize(data, at)? {
+            0 => Ok(AbiValue::Bool(false)),
+            1 => Ok(AbiValue::Bool(true)),
+            _ => Err("invalid bool".to_string()),
+        },
+        AbiType::FixedBytes(len) => Ok(AbiValue::FixedBytes(read_word(data, at)?[..*len].to_vec())),
+        AbiType::Bytes => Ok(AbiValue::Bytes(read_bytes(data, at)?.to_vec())),
+        AbiType::String => String::from_utf8(read_bytes(data, at)?.to_vec())
+            .map(AbiValue::String)
+            .map_err(|err| format!("{}", err)),
+        AbiType::Array(inner) => {
+            let len = read_usize(data, at)?;
+            // every element takes at least a word, bounds a bogus length before allocating
+            if len > data.len() / WORD {
+                return Err("array length out of range".to_string());
+            }
+            let types = vec![*inner.clone(); len];
+            decode_tuple(&types, data, at + WORD).map(AbiValue::Array)
+        }
+        AbiType::FixedArray(inner, len) => {
+            let types = vec![*inner.clone(); *len];
+            decode_tuple(&types, data, at).map(AbiValue::FixedArray)
+        }
+        AbiType::Tuple(types) => decode_tuple(types, data, at).map(AbiValue::Tuple),
+    }
+}
+
+// Maps the Candid argument types of the destination method to ABI types,
+// decodes `data` with them and returns the values as Candid arguments.
+// Candid has no address or fixed size types, so the interface names them with
+// type aliases: `address` is an `address` given as text and `bytes1` to
+// `bytes32` are fixed bytes given as a blob, whatever the alias stands for.
+// Other text is `string` and other blobs `bytes`. Records map to tuples in the
+// order of their field ids, except that a non-empty tuple record whose fields
+// all have the same type is a fixed array, which the ABI encodes the same way.
+pub fn decode_to_idl(data: &[u8], env: &TypeEnv, types: &[Type]) -> Result<Vec<IDLValue>, String> {
+    let abi_types = types
+        .iter()
+        .map(|ty| abi_type(env, ty))
+        .collect::<Result<Vec<_>, _>>()?;
+    decode(&abi_types, data)?
+        .into_iter()
+        .zip(types)
+        .map(|(value, ty)| to_idl(value, env, ty))
+        .collect()
+}
+
+// Maps Candid values to ABI values the way `decode_to_idl` maps them back.
+// Values carry no aliases, so text is always `string` and blobs `bytes`;
+// records are tuples and other vectors dynamic arrays.
+pub fn encode_idl(values: &[IDLValue]) -> Result<Vec<u8>, String> {
+    encode(&values.iter().map(from_idl).collect::<Result<Vec<_>, _>>()?)
+}
+
+fn from_idl(value: &IDLValue) -> Result<AbiValue, String> {
+    Ok(match value {
+        IDLValue::Bool(value) => AbiValue::Bool(*value),
+        IDLValue::Nat(value) => AbiValue::Uint(value.clone()),
+        IDLValue::Nat8(value) => AbiValue::Uint(Nat::from(*value)),
+        IDLValue::Nat16(value) => AbiValue::Uint(Nat::from(*value)),
+        IDLValue::Nat32(value) => AbiValue::Uint(Nat::from(*value)),
+        IDLValue::Nat64(value) => AbiValue::Uint(Nat::from(*value)),
+        IDLValue::Int(value) => AbiValue::Int(value.clone()),
+        IDLValue::Int8(value) => AbiValue::Int(Int::from(*value)),
+        IDLValue::Int16(value) => AbiValue::Int(Int::from(*value)),
+        IDLValue::Int32(value) => AbiValue::Int(Int::from(*value)),
+        IDLValue::Int64(value) => AbiValue::Int(Int::from(*value)),
+        IDLValue::Number(number) => match number.parse::<BigUint>() {
+            Ok(value) => AbiValue::Uint(Nat(value)),
+            Err(_) => AbiValue::Int(Int(number
+                .parse::<BigInt>()
+                .map_err(|err| format!("{}", err))?)),
+        },
+        IDLValue::Text(value) => AbiValue::String(value.clone()),
+        IDLValue::Vec(values)
+            if values
+                .iter()
+                .all(|value| matches!(value, IDLValue::Nat8(_))) =>
+        {
+            AbiValue::Bytes(
+                values
+                    .iter()
+                    .filter_map(|value| match value {
+                        IDLValue::Nat8(byte) => Some(*byte),
+                        _ => None,
+                    })
+                    .collect(),
+            )
+        }
+        IDLValue::Vec(values) => {
+            AbiValue::Array(values.iter().map(from_idl).collect::<Result<_, _>>()?)
+        }
+        IDLValue::Record(fields) => AbiValue::Tuple(
+            fields
+                .iter()
+                .map(|field| from_idl(&field.val))
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err("no ABI value for the Candid value".to_string()),
+    })
+}
+
+fn abi_type(env: &TypeEnv, ty: &Type) -> Result<AbiType, String> {
+    if let Type::Var(name) = ty {
+        return match aliased_type(name) {
+            Some(abi_type) => Ok(abi_type),
+            None => abi_type(env, env.find_type(name).map_err(|err| format!("{}", err))?),
+        };
+    }
+    let ty = env.trace_type(ty).map_err(|err| format!("{}", err))?;
+    Ok(match ty {
+        Type::Bool => AbiType::Bool,
+        Type::Nat => AbiType::Uint(256),
+        Type::Nat8 => AbiType::Uint(8),
+        Type::Nat16 => AbiType::Uint(16),
+        Type::Nat32 => AbiType::Uint(32),
+        Type::Nat64 => AbiType::Uint(64),
+        Type::Int => AbiType::Int(256),
+        Type::Int8 => AbiType::Int(8),
+        Type::Int16 => AbiType::Int(16),
+        Type::Int32 => AbiType::Int(32),
+        Type::Int64 => AbiType::Int(64),
+        Type::Text => AbiType::String,
+        Type::Vec(inner) => match env.trace_type(&inner).map_err(|err| format!("{}", err))? {
+            Type::Nat8 => AbiType::Bytes,
+            _ => AbiType::Array(Box::new(abi_type(env, &inner)?)),
+        },
+        Type::Record(fields) => {
+            let types = fields
+                .iter()
+                .map(|field| abi_type(env, &field.ty))
+                .collect::<Result<Vec<_>, _>>()?;
+            let is_tuple = fields
+                .iter()
+                .enumerate()
+                .all(|(i, field)| field.id.get_id() == i as u32);
+            match types.first() {
+                Some(first) if is_tuple && types.iter().all(|ty| ty == first) => {
+                    AbiType::FixedArray(Box::new(first.clone()), types.len())
+                }
+                _ => AbiType::Tuple(types),
+            }
+        }
+        ty => return Err(format!("no ABI type for {}", ty)),
+    })
+}
+
+fn aliased_type(name: &str) -> Option<AbiType> {
+    if name == "address" {
+        return Some(AbiType::Address);
+    }
+    match name.strip_prefix("bytes")?.parse() {
+        Ok(len) if (1..=WORD).contains(&len) => Some(AbiType::FixedBytes(len)),
+        _ => None,
+    }
+}
+
+fn to_idl(value: AbiValue, env: &TypeEnv, ty: &Type) -> Result<IDLValue, String> {
+    let ty = env.trace_type(ty).map_err(|err| format!("{}", err))?;
+    Ok(match (value, ty) {
+        // left untyped, the encoder gives numbers the exact declared type
+        (AbiValue::Uint(value), _) => IDLValue::Number(value.to_string()),
+        (AbiValue::Int(value), _) => IDLValue::Number(value.to_string()),
+        (AbiValue::Bool(value), _) => IDLValue::Bool(value),
+        (AbiValue::String(value), _) | (AbiValue::Address(value), _) => IDLValue::Text(value),
+        (AbiValue::Bytes(bytes), _) | (AbiValue::FixedBytes(bytes), _) => {
+            IDLValue::Vec(bytes.into_iter().map(IDLValue::Nat8).collect())
+        }
+        (AbiValue::Array(values), Type::Vec(inner)) => IDLValue::Vec(
+            values
+                .into_iter()
+                .map(|value| to_idl(value, env, &inner))
+                .collect::<Result<_, _>>()?,
+        ),
+        (AbiValue::Tuple(values), Type::Record(fields))
+        | (AbiValue::FixedArray(values), Type::Record(fields)) => args::record(
+            values
+                .into_iter()
+                .zip(fields)
+                .map(|(value, field)| {
+                    Ok(IDLField {
+                        val: to_idl(value, env, &field.ty)?,
+                        id: field.id,
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        (_, ty) => return Err(format!("cannot convert ABI value to {}", ty)),
+    })
+}
+
+fn uint_word(value: usize) -> Vec<u8> {
+    left_pad(&value.to_be_bytes(), 0)
+}
+
+fn left_pad(bytes: &[u8], fill: u8) -> Vec<u8> {
+    let mut word = vec![fill; WORD - bytes.len()];
+    word.extend_from_slice(bytes);
+    word
+}
+
+fn right_pad(bytes: &[u8]) -> Vec<u8> {
+    let mut padded = bytes.to_vec();
+    padded.resize(bytes.len().div_ceil(WORD) * WORD, 0);
+    padded
+}
+
+fn read_word(data: &[u8], at: usize) -> Result<&[u8], String> {
+    data.get(at..at.checked_add(WORD).ok_or("offset overflow")?)
+        .ok_or_else(|| "data too short".to_string())
+}
+
+fn read_usize(data: &[u8], at: usize) -> Result<usize, String> {
+    let word = read_word(data, at)?;
+    let (high, low) = word.split_at(WORD - 8);
+    if high.iter().any(|byte| *byte != 0) {
+        return Err("offset out of range".to_string());
+    }
+    Ok(u64::from_be_bytes(low.try_into().unwrap()) as usize)
+}
+
+fn read_bytes(data: &[u8], at: usize) -> Result<&[u8], String> {
+    let len = read_usize(data, at)?;
+    let start = at + WORD;
+    data.get(start..start.checked_add(len).ok_or("length overflow")?)
+        .ok_or_else(|| "data too short".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uint(value: u64) -> AbiValue {
+        AbiValue::Uint(Nat::from(value))
+    }
+
+    fn words(words: &[&str]) -> Vec<u8> {
+        hex::decode(words.concat()).unwrap()
+    }
+
+    fn round_trip(types: &[AbiType], values: Vec<AbiValue>, expected: Vec<u8>) {
+        assert_eq!(encode(&values).unwrap(), expected);
+        assert_eq!(decode(types, &expected).unwrap(), values);
+    }
+
+    // baz(uint32,bool) from the ABI spec
+    #[test]
+    fn static_arguments() {
+        round_trip(
+            &[AbiType::Uint(32), AbiType::Bool],
+            vec![uint(69), AbiValue::Bool(true)],
+            words(&[
+                "0000000000000000000000000000000000000000000000000000000000000045",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+            ]),
+        );
+    }
+
+    // sam(bytes,bool,uint256[]) from the ABI spec
+    #[test]
+    fn dynamic_arguments() {
+        round_trip(
+            &[
+                AbiType::Bytes,
+                AbiType::Bool,
+                AbiType::Array(Box::new(AbiType::Uint(256))),
+            ],
+            vec![
+                AbiValue::Bytes(b"dave".to_vec()),
+                AbiValue::Bool(true),
+                AbiValue::Array(vec![uint(1), uint(2), uint(3)]),
+            ],
+            words(&[
+                "0000000000000000000000000000000000000000000000000000000000000060",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "00000000000000000000000000000000000000000000000000000000000000a0",
+                "0000000000000000000000000000000000000000000000000000000000000004",
+                "6461766500000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000003",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "0000000000000000000000000000000000000000000000000000000000000003",
+            ]),
+        );
+    }
+
+    // f(uint256,uint32[],bytes10,bytes) from the ABI spec
+    #[test]
+    fn mixed_arguments() {
+        round_trip(
+            &[
+                AbiType::Uint(256),
+                AbiType::Array(Box::new(AbiType::Uint(32))),
+                AbiType::FixedBytes(10),
+                AbiType::Bytes,
+            ],
+            vec![
+                uint(0x123),
+                AbiValue::Array(vec![uint(0x456), uint(0x789)]),
+                AbiValue::FixedBytes(b"1234567890".to_vec()),
+                AbiValue::Bytes(b"Hello, world!".to_vec()),
+            ],
+            words(&[
+                "0000000000000000000000000000000000000000000000000000000000000123",
+                "0000000000000000000000000000000000000000000000000000000000000080",
+                "3132333435363738393000000000000000000000000000000000000000000000",
+                "00000000000000000000000000000000000000000000000000000000000000e0",
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "0000000000000000000000000000000000000000000000000000000000000456",
+                "0000000000000000000000000000000000000000000000000000000000000789",
+                "000000000000000000000000000000000000000000000000000000000000000d",
+                "48656c6c6f2c20776f726c642100000000000000000000000000000000000000",
+            ]),
+        );
+    }
+
+    // g(uint256[][],string[]) from the ABI spec
+    #[test]
+    fn nested_dynamic_arguments() {
+        round_trip(
+            &[
+                AbiType::Array(Box::new(AbiType::Array(Box::new(AbiType::Uint(256))))),
+                AbiType::Array(Box::new(AbiType::String)),
+            ],
+            vec![
+                AbiValue::Array(vec![
+                    AbiValue::Array(vec![uint(1), uint(2)]),
+                    AbiValue::Array(vec![uint(3)]),
+                ]),
+                AbiValue::Array(vec![
+                    AbiValue::String("one".to_string()),
+                    AbiValue::String("two".to_string()),
+                    AbiValue::String("three".to_string()),
+                ]),
+            ],
+            words(&[
+                "0000000000000000000000000000000000000000000000000000000000000040",
+                "0000000000000000000000000000000000000000000000000000000000000140",
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "0000000000000000000000000000000000000000000000000000000000000040",
+                "00000000000000000000000000000000000000000000000000000000000000a0",
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000002",
+                "0000000000000000000000000000000000000000000000000000000000000001",
+                "0000000000000000000000000000000000000000000000000000000000000003",
+                "0000000000000000000000000000000000000000000000000000000000000003",
+                "0000000000000000000000000000000000000000000000000000000000000060",
+                "00000000000000000000000000000000000000000000000000000000000000a0",
+                "00000000000000000000000000000000000000000000000000000000000000e0",
+                "0000000000000000000000000000000000000000000000000000000000000003",
+                "6f6e650000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000003",
+                "74776f0000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000005",
+                "7468726565000000000000000000000000000000000000000000000000000000",
+            ]),
+        );
+    }
+
+    #[test]
+    fn signed_and_address_arguments() {
+        round_trip(
+            &[AbiType::Int(256), AbiType::Int(8), AbiType::Address],
+            vec![
+                AbiValue::Int(Int::from(-1)),
+                AbiValue::Int(Int::from(-128)),
+                AbiValue::Address("0x5b38da6a701c568545dcfcb03fcb875f56beddc4".to_string()),
+            ],
+            words(&[
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff80",
+                "0000000000000000000000005b38da6a701c568545dcfcb03fcb875f56beddc4",
+            ]),
+        );
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        let data = encode(&[uint(256)]).unwrap();
+        assert!(decode(&[AbiType::Uint(8)], &data).is_err());
+        assert!(decode(&[AbiType::Bytes], &data).is_err());
+    }
+
+    #[test]
+    fn decodes_into_candid_arguments() {
+        let interface =
+            r#"service : { "receive": (nat64, text, record { nat8; bool }, record {}) -> () }"#;
+        let (env, types) = args::method_arg_types(interface, "receive").unwrap();
+        let data = encode(&[
+            uint(7),
+            AbiValue::String("hi".to_string()),
+            AbiValue::Tuple(vec![uint(1), AbiValue::Bool(false)]),
+        ])
+        .unwrap();
+        // the trailing context record is not part of the payload
+        let mut values = decode_to_idl(&data, &env, &types[..types.len() - 1]).unwrap();
+        values.push(IDLValue::Record(vec![]));
+        let bytes = candid::IDLArgs { args: values }
+            .to_bytes_with_types(&env, &types)
+            .unwrap();
+        let (id, text, (small, flag)): (u64, String, (u8, bool)) =
+            candid::decode_args(&bytes).unwrap();
+        assert_eq!((id, text.as_str(), small, flag), (7, "hi", 1, false));
+    }
+    #[test]
+    fn decodes_aliased_types_into_candid_arguments() {
+        let interface = r#"
+            type address = text;
+            type bytes32 = blob;
+            service : { "receive": (address, bytes32, record { nat; nat }) -> () }
+        "#;
+        let (env, types) = args::method_arg_types(interface, "receive").unwrap();
+        let address = "0x5b38da6a701c568545dcfcb03fcb875f56beddc4";
+        let data = encode(&[
+            AbiValue::Address(address.to_string()),
+            AbiValue::FixedBytes(vec![0xab; 32]),
+            AbiValue::FixedArray(vec![uint(1), uint(2)]),
+        ])
+        .unwrap();
+        // all three are static, an address or bytes32 read as string or bytes
+        // would have taken an offset word instead
+        assert_eq!(data.len(), 4 * WORD);
+        let values = decode_to_idl(&data, &env, &types).unwrap();
+        let bytes = candid::IDLArgs { args: values }
+            .to_bytes_with_types(&env, &types)
+            .unwrap();
+        let (to, hash, (first, second)): (String, Vec<u8>, (candid::Nat, candid::Nat)) =
+            candid::decode_args(&bytes).unwrap();
+        assert_eq!(to, address);
+        assert_eq!(hash, vec![0xab; 32]);
+        assert_eq!(
+            (first, second),
+            (candid::Nat::from(1), candid::Nat::from(2))
+        );
+    }
+}
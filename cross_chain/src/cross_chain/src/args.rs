@@ -0,0 +1,236 @@
+use crate::{abi, PayloadEncoding};
+use candid::{
+    check_prog,
+    parser::value::{IDLField, IDLValue},
+    types::{Function, Label, Type},
+    IDLArgs, IDLProg, TypeEnv,
+};
+use serde_json::Value;
+
+// Candid wants record fields ordered by label hash
+pub fn record(mut fields: Vec<IDLField>) -> IDLValue {
+    fields.sort_by_key(|field| field.id.get_id());
+    IDLValue::Record(fields)
+}
+
+// Encodes the payload `data` followed by `context` as the arguments of
+// `method`. With the destination's interface the values take the declared
+// types, e.g. `nat64` instead of the `nat` the text parser would pick.
+pub fn encode_call_args(
+    data: &str,
+    encoding: PayloadEncoding,
+    context: IDLValue,
+    interface: Option<&str>,
+    method: &str,
+) -> Result<Vec<u8>, String> {
+    let types = interface
+        .map(|interface| method_arg_types(interface, method))
+        .transpose()?;
+    let mut args = match (encoding, &types) {
+        // the trailing parameter takes the context
+        (PayloadEncoding::EvmAbi, Some((env, types))) => {
+            let bytes = hex::decode(data).map_err(|err| format!("{}", err))?;
+            IDLArgs {
+                args: abi::decode_to_idl(&bytes, env, &types[..types.len().saturating_sub(1)])?,
+            }
+        }
+        _ => decode_payload(data, encoding)?,
+    };
+    args.args.push(context);
+    match types {
+        Some((env, types)) => args.to_bytes_with_types(&env, &types),
+        None => args.to_bytes(),
+    }
+    .map_err(|err| format!("{}", err))
+}
+
+// ABI payloads can only be decoded against the destination's types, so
+// outbound ones are only checked for well-formed words
+pub fn check_payload(data: &str, encoding: PayloadEncoding) -> Result<(), String> {
+    match encoding {
+        PayloadEncoding::EvmAbi => {
+            let bytes = hex::decode(data).map_err(|err| format!("{}", err))?;
+            if bytes.len() % 32 != 0 {
+                return Err("ABI payload is not made of 32 byte words".to_string());
+            }
+            Ok(())
+        }
+        _ => decode_payload(data, encoding).map(|_| ()),
+    }
+}
+
+pub fn decode_payload(data: &str, encoding: PayloadEncoding) -> Result<IDLArgs, String> {
+    match encoding {
+        PayloadEncoding::CandidText => data.parse().map_err(|err| format!("{}", err)),
+        PayloadEncoding::CandidBinary => {
+            let bytes = hex::decode(data).map_err(|err| format!("{}", err))?;
+            IDLArgs::from_bytes(&bytes).map_err(|err| format!("{}", err))
+        }
+        // a top level array holds the positional arguments
+        PayloadEncoding::Json => match serde_json::from_str(data) {
+            Ok(Value::Array(values)) => Ok(IDLArgs {
+                args: values.into_iter().map(json_to_idl).collect(),
+            }),
+            Ok(value) => Ok(IDLArgs {
+                args: vec![json_to_idl(value)],
+            }),
+            Err(err) => Err(format!("{}", err)),
+        },
+        // handed over as a single blob
+        PayloadEncoding::Raw => {
+            let bytes = hex::decode(data).map_err(|err| format!("{}", err))?;
+            Ok(IDLArgs {
+                args: vec![IDLValue::Vec(
+                    bytes.into_iter().map(IDLValue::Nat8).collect(),
+                )],
+            })
+        }
+        PayloadEncoding::EvmAbi => Err("ABI payload needs the destination interface".to_string()),
+    }
+}
+
+// The reverse of `decode_payload`, for replies sent back to the source chain.
+// Raw payloads are Borsh, which can't be built without the receiver's schema.
+pub fn encode_payload(args: &IDLArgs, encoding: PayloadEncoding) -> Result<String, String> {
+    match encoding {
+        PayloadEncoding::CandidText => Ok(args.to_string()),
+        PayloadEncoding::CandidBinary => args
+            .to_bytes()
+            .map(hex::encode)
+            .map_err(|err| format!("{}", err)),
+        PayloadEncoding::Json => Ok(Value::Array(
+            args.args
+                .iter()
+                .map(idl_to_json)
+                .collect::<Result<_, _>>()?,
+        )
+        .to_string()),
+        PayloadEncoding::Raw => Err("Raw payloads can't be built from Candid values".to_string()),
+        PayloadEncoding::EvmAbi => abi::encode_idl(&args.args).map(hex::encode),
+    }
+}
+
+// numbers stay untyped so they can take the type the interface declares
+fn json_to_idl(value: Value) -> IDLValue {
+    match value {
+        Value::Null => IDLValue::Null,
+        Value::Bool(value) => IDLValue::Bool(value),
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() => IDLValue::Float64(float),
+            _ => IDLValue::Number(number.to_string()),
+        },
+        Value::String(value) => IDLValue::Text(value),
+        Value::Array(values) => IDLValue::Vec(values.into_iter().map(json_to_idl).collect()),
+        Value::Object(fields) => record(
+            fields
+                .into_iter()
+                .map(|(name, value)| IDLField {
+                    id: Label::Named(name),
+                    val: json_to_idl(value),
+                })
+                .collect(),
+        ),
+    }
+}
+
+// Numbers that fit are JSON numbers, larger ones strings as `near-sdk` takes
+// `U128`. Records keep their field names only when decoded with types.
+fn idl_to_json(value: &IDLValue) -> Result<Value, String> {
+    Ok(match value {
+        IDLValue::Null | IDLValue::None | IDLValue::Reserved => Value::Null,
+        IDLValue::Bool(value) => Value::Bool(*value),
+        IDLValue::Text(value) => Value::String(value.clone()),
+        IDLValue::Principal(principal) => Value::String(principal.to_text()),
+        IDLValue::Number(number) => json_number(number),
+        IDLValue::Nat(nat) => json_number(&nat.to_string()),
+        IDLValue::Int(int) => json_number(&int.to_string()),
+        IDLValue::Nat8(value) => Value::from(*value),
+        IDLValue::Nat16(value) => Value::from(*value),
+        IDLValue::Nat32(value) => Value::from(*value),
+        IDLValue::Nat64(value) => Value::from(*value),
+        IDLValue::Int8(value) => Value::from(*value),
+        IDLValue::Int16(value) => Value::from(*value),
+        IDLValue::Int32(value) => Value::from(*value),
+        IDLValue::Int64(value) => Value::from(*value),
+        IDLValue::Float32(value) => Value::from(*value),
+        IDLValue::Float64(value) => Value::from(*value),
+        IDLValue::Opt(value) => idl_to_json(value)?,
+        IDLValue::Vec(values) => {
+            Value::Array(values.iter().map(idl_to_json).collect::<Result<_, _>>()?)
+        }
+        // tuples
+        IDLValue::Record(fields)
+            if fields
+                .iter()
+                .all(|field| matches!(field.id, Label::Unnamed(_))) =>
+        {
+            Value::Array(
+                fields
+                    .iter()
+                    .map(|field| idl_to_json(&field.val))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        IDLValue::Record(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|field| Ok((field.id.to_string(), idl_to_json(&field.val)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        IDLValue::Variant(variant) => {
+            let mut object = serde_json::Map::new();
+            object.insert(variant.0.id.to_string(), idl_to_json(&variant.0.val)?);
+            Value::Object(object)
+        }
+        IDLValue::Service(_) | IDLValue::Func(..) => {
+            return Err("references have no JSON form".to_string())
+        }
+    })
+}
+
+fn json_number(number: &str) -> Value {
+    number
+        .parse::<i64>()
+        .map(Value::from)
+        .or_else(|_| number.parse::<u64>().map(Value::from))
+        .unwrap_or_else(|_| Value::String(number.to_string()))
+}
+
+pub fn method_arg_types(interface: &str, method: &str) -> Result<(TypeEnv, Vec<Type>), String> {
+    let (env, function) = method_type(interface, method)?;
+    Ok((env, function.args))
+}
+
+// Decodes the reply of `method`, with the field names the interface declares
+// when there is one.
+pub fn decode_reply(
+    reply: &[u8],
+    interface: Option<&str>,
+    method: &str,
+) -> Result<IDLArgs, String> {
+    match interface {
+        Some(interface) => {
+            let (env, function) = method_type(interface, method)?;
+            IDLArgs::from_bytes_with_types(reply, &env, &function.rets)
+        }
+        None => IDLArgs::from_bytes(reply),
+    }
+    .map_err(|err| format!("{}", err))
+}
+
+fn method_type(interface: &str, method: &str) -> Result<(TypeEnv, Function), String> {
+    let mut env = TypeEnv::new();
+    let actor = check_interface(&mut env, interface)?;
+    let function = env
+        .get_method(&actor, method)
+        .map_err(|err| format!("{}", err))?
+        .clone();
+    Ok((env, function))
+}
+
+pub fn check_interface(env: &mut TypeEnv, interface: &str) -> Result<Type, String> {
+    let prog: IDLProg = interface.parse().map_err(|err| format!("{}", err))?;
+    check_prog(env, &prog)
+        .map_err(|err| format!("{}", err))?
+        .ok_or_else(|| "interface has no service".to_string())
+}
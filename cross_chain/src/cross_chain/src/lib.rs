@@ -1,45 +1,174 @@
 use candid::{
+    de::IDLDeserialize,
     parser::value::{IDLField, IDLValue},
     types::Label,
-    IDLArgs,
+    IDLArgs, TypeEnv,
 };
 use ic_cdk::{
-    api,
+    api::{
+        self,
+        call::{CallResult, RejectionCode},
+    },
     export::{candid::CandidType, Principal},
 };
 use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use memory::Memory;
 use serde::{Deserialize, Serialize};
 use serde_cbor::Serializer;
 use sha2::{Digest, Sha256};
-use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
 use std::result::Result as StdResult;
 
+mod abi;
+mod args;
+mod memory;
+
+// Saved with candid on upgrade, which only accepts a field missing from the
+// saved bytes when it is `opt`. A layout change bumps `STATE_VERSION` and
+// keeps the previous layout as a frozen struct that `restore_state` converts.
 #[derive(CandidType, Deserialize, Default)]
 struct State {
     custodians: HashSet<Principal>,
     lockers: HashSet<Principal>,
-    pending_message: BTreeMap<MapKey, BTreeMap<String, PendingMessage>>,
-    sent_message: BTreeMap<MapKey, Message>,
-    executable_message: BTreeMap<MapKey, Message>,
     sent_message_count: HashMap<String, u64>,
     final_received_message_id: BTreeMap<MapKey, u64>,
     latest_message_id: HashMap<String, u64>,
-    validators: HashSet<Principal>,
+    // validators registered per source chain
+    chain_validators: HashMap<String, HashSet<Principal>>,
+    // bumped whenever a chain's validator set changes
+    validator_epoch: HashMap<String, u64>,
+    // votes for the same message hash needed to make a message executable,
+    // 0 means every registered validator
+    quorum_threshold: u64,
+    // every message up to this id was agreed on, by source chain
+    agreed_message_id: HashMap<String, u64>,
+    // messages agreed on past `agreed_message_id`, until the gap before them closes
+    agreed_message: BTreeSet<MapKey>,
+    // messages that received votes for more than one hash
+    conflicts: BTreeMap<MapKey, ConflictEvidence>,
+    freeze_on_conflict: bool,
+    // conflicting messages held back from execution until a custodian resolves them
+    frozen_message: BTreeSet<MapKey>,
+    // executable messages whose destination call is in flight
+    executing_message: BTreeSet<MapKey>,
+    // delivery attempts before a failed message is dead-lettered, 0 means the default
+    max_execution_attempts: u32,
+    // Candid interface (.did source) of destination canisters, by principal text
+    contract_interface: HashMap<String, String>,
+    // chain name of this canister, `from_chain` of outbound and `to_chain` of inbound messages
+    local_chain: String,
+    // chains messages are exchanged with, by name
+    chain_registry: BTreeMap<String, ChainConfig>,
+    // execute messages from the heartbeat as soon as they become executable
+    auto_execute: bool,
+    // messages executed per heartbeat, 0 means the default
+    auto_execute_batch: u64,
+    // last message delivered or given up on, by chain with ordered delivery
+    delivered_message_id: HashMap<String, u64>,
 }
 
+// message maps live in stable memory so they neither hit the heap limit nor
+// have to be serialized on upgrade
 thread_local! {
     static STATE: RefCell<State> = RefCell::default();
+    static PENDING_MESSAGE: RefCell<StableBTreeMap<MapKey, PendingEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory::get(memory::PENDING_MESSAGE_MEMORY_ID)),
+    );
+    static SENT_MESSAGE: RefCell<StableBTreeMap<MapKey, Message, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory::get(memory::SENT_MESSAGE_MEMORY_ID)),
+    );
+    static EXECUTABLE_MESSAGE: RefCell<StableBTreeMap<MapKey, Message, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory::get(memory::EXECUTABLE_MESSAGE_MEMORY_ID)),
+    );
+    static FAILED_MESSAGE: RefCell<StableBTreeMap<MapKey, FailedMessage, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory::get(memory::FAILED_MESSAGE_MEMORY_ID)),
+    );
+    static EXECUTED_MESSAGE: RefCell<StableBTreeMap<MapKey, ExecutionReceipt, Memory>> = RefCell::new(
+        StableBTreeMap::init(memory::get(memory::EXECUTED_MESSAGE_MEMORY_ID)),
+    );
 }
 
+// progress of heartbeat execution, only meaningful while the canister runs
+thread_local! {
+    static AUTO_EXECUTING: Cell<bool> = const { Cell::new(false) };
+    // last message the heartbeat took up, the next batch starts after it
+    static AUTO_EXECUTE_CURSOR: RefCell<Option<MapKey>> = const { RefCell::new(None) };
+}
+
+// bump when the layout of `State` changes in a way `post_upgrade` must migrate
+const STATE_VERSION: u32 = 1;
+
+const DEFAULT_MAX_EXECUTION_ATTEMPTS: u32 = 3;
+
+// local chain of the deployed build, which hard-coded it
+const LEGACY_LOCAL_CHAIN: &str = "DFINITY";
+
+const DEFAULT_AUTO_EXECUTE_BATCH: u64 = 10;
+
+// entries returned by a paginated query, also the page size when 0 is asked for
+const MAX_PAGE_SIZE: u64 = 100;
+// messages a `receiveMessages` batch may carry, keeps a batch within the
+// instruction limit of one update call
+const MAX_RECEIVE_BATCH: u64 = 100;
+
+// `Session.res_type` of a message whose sender wants the destination's reply
+const SESSION_REQUEST: u8 = 1;
+// `Session.res_type` of the message carrying that reply back
+const SESSION_RESPONSE: u8 = 2;
+// `Session.res_type` of a message whose sender only wants to hear about failures
+const SESSION_ERROR_CALLBACK: u8 = 3;
+// `Session.res_type` of the message reporting a failed delivery to requests and error callbacks
+const SESSION_ERROR: u8 = 4;
+
 #[init]
-fn init() {
+fn init(local_chain: String) {
+    assert!(!local_chain.is_empty(), "local chain name is empty");
     STATE.with(|state| {
         let mut state = state.borrow_mut();
         state.custodians = HashSet::from([api::caller()]);
+        state.local_chain = local_chain;
     })
 }
 
+#[pre_upgrade]
+fn pre_upgrade() {
+    STATE.with(|state| {
+        let bytes = candid::utils::encode_args((STATE_VERSION, &*state.borrow()))
+            .expect("save state failed");
+        memory::write_upgrade_bytes(&bytes);
+    })
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let restored = match memory::read_upgrade_bytes() {
+        Some(bytes) => restore_state(&bytes),
+        // the deployed build had no upgrade hooks, none of its state survived
+        None => State {
+            custodians: HashSet::from([api::caller()]),
+            local_chain: LEGACY_LOCAL_CHAIN.to_string(),
+            ..Default::default()
+        },
+    };
+    STATE.with(|state| *state.borrow_mut() = restored);
+}
+
+// Layouts saved before versioning were never deployed and aren't restored.
+fn restore_state(bytes: &[u8]) -> State {
+    let mut de = IDLDeserialize::new(bytes).expect("restore state failed");
+    let version: u32 = de.get_value().expect("restore state version failed");
+    let restored = match version {
+        STATE_VERSION => de.get_value::<State>(),
+        _ => panic!("unsupported state version {}", version),
+    }
+    .expect("restore state failed");
+    de.done().expect("restore state failed");
+    restored
+}
+
 #[update(name = "registerLocker")]
 fn register_locker(locker: Principal) -> Result {
     STATE.with(|state| {
@@ -54,12 +183,20 @@ fn register_locker(locker: Principal) -> Result {
 }
 
 #[update(name = "registerValidator")]
-fn register_validator(validator: Principal) -> Result {
+fn register_validator(chain_name: String, validator: Principal) -> Result {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
         let caller = api::caller();
         if state.custodians.contains(&caller) {
-            Ok(state.validators.insert(validator))
+            let inserted = state
+                .chain_validators
+                .entry(chain_name.clone())
+                .or_default()
+                .insert(validator);
+            if inserted {
+                *state.validator_epoch.entry(chain_name).or_default() += 1;
+            }
+            Ok(inserted)
         } else {
             Err(Error::Unauthorized)
         }
@@ -67,182 +204,964 @@ fn register_validator(validator: Principal) -> Result {
 }
 
 #[update(name = "unRegisterValidator")]
-fn un_register_validator(validator: Principal) -> Result {
+fn un_register_validator(chain_name: String, validator: Principal) -> Result {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
         let caller = api::caller();
         if state.custodians.contains(&caller) {
-            Ok(state.validators.remove(&validator))
+            let removed = state
+                .chain_validators
+                .get_mut(&chain_name)
+                .is_some_and(|validators| validators.remove(&validator));
+            if removed {
+                *state.validator_epoch.entry(chain_name.clone()).or_default() += 1;
+                // the removed validator may have been the last vote missing
+                promote_agreed_messages(&mut state, &chain_name);
+            }
+            Ok(removed)
         } else {
             Err(Error::Unauthorized)
         }
     })
 }
 
-#[update(name = "receiveMessage")]
-fn receive_message(id: u64, message: Message) -> Result {
-    let validator = api::caller();
-    if is_validator(&validator) {
-        STATE.with(|state| {
-            let final_received_key = MapKey::ValidatorFinalReceivedId {
-                chain_name: message.from_chain.clone(),
-                validator,
-            };
-            let message_hash = message.to_hash();
-            let mut state = state.borrow_mut();
-            // let state = &mut *state;
-            let latest_message_id = *state
-                .latest_message_id
-                .get(&message.from_chain)
-                .unwrap_or(&0u64);
-            if id == latest_message_id + 1 {
-                state
-                    .latest_message_id
-                    .insert(message.from_chain.clone(), id);
-            }
-            if id > latest_message_id + 1 {
-                panic!("id not <= {}", latest_message_id + 1);
-            }
-            let final_received_message_id = state
-                .final_received_message_id
-                .get(&final_received_key)
-                .unwrap_or(&0u64);
-            assert_ne!(*final_received_message_id, id, "already received");
-            let received_key = MapKey::MessageId {
-                chain_name: message.from_chain.clone(),
-                id,
-            };
-            // 前面存在有节点未完成搬运时帮其搬运，得确保搬运消息存在，防止重复搬运
-            if id < *final_received_message_id
-                || (id < latest_message_id + 1 && *final_received_message_id == 0)
-            {
-                match state.pending_message.get(&received_key) {
-                    None => {
-                        panic!("this message has completed");
-                    }
-                    _ => {}
-                }
+// registers a chain or replaces its configuration, Ok(false) when it was already registered
+#[update(name = "registerChain")]
+fn register_chain(chain_name: String, config: ChainConfig) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if !state.custodians.contains(&caller) {
+            return Err(Error::Unauthorized);
+        }
+        if !config.quorum.as_ref().is_none_or(Quorum::is_valid) {
+            return Err(Error::InvalidQuorum);
+        }
+        let is_new = state
+            .chain_registry
+            .insert(chain_name.clone(), config)
+            .is_none();
+        track_delivered(&mut state, &chain_name);
+        Ok(is_new)
+    })
+}
+
+// messages already received from the chain are still executed
+#[update(name = "unRegisterChain")]
+fn un_register_chain(chain_name: String) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if state.custodians.contains(&caller) {
+            state.delivered_message_id.remove(&chain_name);
+            Ok(state.chain_registry.remove(&chain_name).is_some())
+        } else {
+            Err(Error::Unauthorized)
+        }
+    })
+}
+
+// a disabled chain neither sends nor receives messages
+#[update(name = "setChainEnabled")]
+fn set_chain_enabled(chain_name: String, enabled: bool) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if !state.custodians.contains(&caller) {
+            return Err(Error::Unauthorized);
+        }
+        match state.chain_registry.get_mut(&chain_name) {
+            Some(config) => {
+                config.enabled = enabled;
+                Ok(true)
             }
-            if id > *final_received_message_id {
-                state
-                    .final_received_message_id
-                    .insert(final_received_key, id);
+            None => Err(Error::ChainNotRegistered),
+        }
+    })
+}
+
+#[update(name = "setQuorumThreshold")]
+fn set_quorum_threshold(threshold: u64) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if state.custodians.contains(&caller) {
+            state.quorum_threshold = threshold;
+            Ok(true)
+        } else {
+            Err(Error::Unauthorized)
+        }
+    })
+}
+
+#[update(name = "setChainOrderedDelivery")]
+fn set_chain_ordered_delivery(chain_name: String, ordered: bool) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if !state.custodians.contains(&caller) {
+            return Err(Error::Unauthorized);
+        }
+        match state.chain_registry.get_mut(&chain_name) {
+            Some(config) => config.ordered_delivery = ordered,
+            None => return Err(Error::ChainNotRegistered),
+        }
+        track_delivered(&mut state, &chain_name);
+        Ok(true)
+    })
+}
+
+#[update(name = "setChainQuorum")]
+fn set_chain_quorum(chain_name: String, quorum: Quorum) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if !state.custodians.contains(&caller) {
+            return Err(Error::Unauthorized);
+        }
+        if !quorum.is_valid() {
+            return Err(Error::InvalidQuorum);
+        }
+        match state.chain_registry.get_mut(&chain_name) {
+            Some(config) => {
+                config.quorum = Some(quorum);
+                Ok(true)
             }
-            match state.pending_message.get_mut(&received_key) {
-                Some(map) => {
-                    if map.contains_key(&message_hash) {
-                        let group: &mut PendingMessage = map.get_mut(&message_hash).unwrap();
-                        assert!(
-                            !group.validators.contains(&validator),
-                            "{} already recived message",
-                            validator.to_text()
-                        );
-                        group.validators.push(validator);
-                    } else {
-                        map.insert(
-                            message_hash,
-                            PendingMessage {
-                                message: message.clone(),
-                                validators: vec![validator],
-                            },
-                        );
-                    }
-                }
-                None => {
-                    state.pending_message.insert(
-                        received_key.clone(),
-                        BTreeMap::from([(
-                            message_hash,
-                            PendingMessage {
-                                message: message.clone(),
-                                validators: vec![validator],
-                            },
-                        )]),
-                    );
+            None => Err(Error::ChainNotRegistered),
+        }
+    })
+}
+
+#[update(name = "removeChainQuorum")]
+fn remove_chain_quorum(chain_name: String) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if state.custodians.contains(&caller) {
+            Ok(state
+                .chain_registry
+                .get_mut(&chain_name)
+                .and_then(|config| config.quorum.take())
+                .is_some())
+        } else {
+            Err(Error::Unauthorized)
+        }
+    })
+}
+
+#[update(name = "setFreezeOnConflict")]
+fn set_freeze_on_conflict(freeze: bool) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if state.custodians.contains(&caller) {
+            state.freeze_on_conflict = freeze;
+            Ok(true)
+        } else {
+            Err(Error::Unauthorized)
+        }
+    })
+}
+
+// Unfreezes a conflicting message. With a hash, that group's message becomes
+// executable right away, otherwise voting carries on as usual.
+#[update(name = "resolveConflict")]
+fn resolve_conflict(chain_name: String, id: u64, message_hash: Option<String>) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if !state.custodians.contains(&caller) {
+            return Err(Error::Unauthorized);
+        }
+        let key = MapKey::MessageId {
+            chain_name: chain_name.clone(),
+            id,
+        };
+        if !state.conflicts.contains_key(&key) {
+            return Err(Error::ConflictNotFound);
+        }
+        if let Some(message_hash) = message_hash {
+            let message = PENDING_MESSAGE
+                .with(|pending| pending.borrow().get(&key))
+                .and_then(|entry| entry.groups.get(&message_hash).cloned())
+                .map(|group| group.message);
+            match message {
+                Some(message) => {
+                    PENDING_MESSAGE.with(|pending| pending.borrow_mut().remove(&key));
+                    EXECUTABLE_MESSAGE
+                        .with(|executable| executable.borrow_mut().insert(key.clone(), message));
+                    mark_agreed(&mut state, &key);
                 }
+                None => return Err(Error::ConflictNotFound),
             }
-            let mut len = 0;
-            for (_, group) in state.pending_message.get(&received_key).unwrap() {
-                len += group.validators.len();
-            }
-            if len >= state.validators.len() {
-                state
-                    .executable_message
-                    .insert(received_key.clone(), message);
-                state.pending_message.remove(&received_key);
+        }
+        state.frozen_message.remove(&key);
+        if let Some(evidence) = state.conflicts.get_mut(&key) {
+            evidence.resolved = true;
+        }
+        promote_agreed_messages(&mut state, &chain_name);
+        Ok(true)
+    })
+}
+
+#[update(name = "setAutoExecute")]
+fn set_auto_execute(enabled: bool, batch: u64) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if state.custodians.contains(&caller) {
+            state.auto_execute = enabled;
+            state.auto_execute_batch = batch;
+            Ok(true)
+        } else {
+            Err(Error::Unauthorized)
+        }
+    })
+}
+
+#[update(name = "setMaxExecutionAttempts")]
+fn set_max_execution_attempts(attempts: u32) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if state.custodians.contains(&caller) {
+            state.max_execution_attempts = attempts;
+            Ok(true)
+        } else {
+            Err(Error::Unauthorized)
+        }
+    })
+}
+
+// a destination canister may register its own interface
+#[update(name = "registerInterface")]
+fn register_interface(contract: String, interface: String) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if !state.custodians.contains(&caller) && caller.to_text() != contract {
+            return Err(Error::Unauthorized);
+        }
+        if args::check_interface(&mut TypeEnv::new(), &interface).is_err() {
+            return Err(Error::InvalidInterface);
+        }
+        state.contract_interface.insert(contract, interface);
+        Ok(true)
+    })
+}
+
+#[update(name = "unRegisterInterface")]
+fn un_register_interface(contract: String) -> Result {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let caller = api::caller();
+        if state.custodians.contains(&caller) || caller.to_text() == contract {
+            Ok(state.contract_interface.remove(&contract).is_some())
+        } else {
+            Err(Error::Unauthorized)
+        }
+    })
+}
+
+// reads the interface the destination canister publishes about itself
+#[update(name = "fetchInterface")]
+async fn fetch_interface(contract: String) -> Result {
+    let caller = api::caller();
+    let authorized = STATE.with(|state| state.borrow().custodians.contains(&caller))
+        || caller.to_text() == contract;
+    if !authorized {
+        return Err(Error::Unauthorized);
+    }
+    let canister = Principal::from_text(&contract).map_err(|_| Error::InvalidInterface)?;
+    let (interface,): (String,) = api::call::call(canister, "__get_candid_interface_tmp_hack", ())
+        .await
+        .map_err(|_| Error::InvalidInterface)?;
+    if args::check_interface(&mut TypeEnv::new(), &interface).is_err() {
+        return Err(Error::InvalidInterface);
+    }
+    STATE.with(|state| {
+        state
+            .borrow_mut()
+            .contract_interface
+            .insert(contract, interface)
+    });
+    Ok(true)
+}
+
+#[update(name = "receiveMessage")]
+fn receive_message(id: u64, message: Message) -> Result {
+    receive(api::caller(), id, message)
+}
+
+// Entries are applied in order, each as a `receiveMessage` call of its own: a
+// failed entry leaves the state untouched and doesn't stop the following ones.
+#[update(name = "receiveMessages")]
+fn receive_messages(messages: Vec<ReceivedMessage>) -> Result<Vec<Result>> {
+    receive_batch(api::caller(), messages)
+}
+
+fn receive_batch(validator: Principal, messages: Vec<ReceivedMessage>) -> Result<Vec<Result>> {
+    if messages.len() as u64 > MAX_RECEIVE_BATCH {
+        return Err(Error::BatchTooLarge {
+            max: MAX_RECEIVE_BATCH,
+        });
+    }
+    Ok(messages
+        .into_iter()
+        .map(|ReceivedMessage { id, message }| receive(validator, id, message))
+        .collect())
+}
+
+// records `validator`'s vote for message `id` from `message.from_chain`
+fn receive(validator: Principal, id: u64, message: Message) -> Result {
+    if !is_validator(&validator, &message.from_chain) {
+        return Err(Error::NotValidator);
+    }
+    STATE.with(|state| {
+        let final_received_key = MapKey::ValidatorFinalReceivedId {
+            chain_name: message.from_chain.clone(),
+            validator,
+        };
+        let message_hash = message.to_hash();
+        let mut state = state.borrow_mut();
+        // every check runs before the first write, an error must leave the state untouched
+        if message.to_chain != state.local_chain {
+            return Err(Error::InvalidToChain);
+        }
+        match state.chain_registry.get(&message.from_chain) {
+            None => return Err(Error::UnknownFromChain),
+            Some(config) if !config.enabled => return Err(Error::ChainDisabled),
+            Some(config) if !config.address_format.is_valid(&message.sender) => {
+                return Err(Error::InvalidAddress)
             }
+            _ => {}
+        }
+        if Principal::from_text(&message.content.contract).is_err() {
+            return Err(Error::InvalidContract);
+        }
+        let latest_message_id = *state
+            .latest_message_id
+            .get(&message.from_chain)
+            .unwrap_or(&0u64);
+        if id > latest_message_id + 1 {
+            return Err(Error::IdTooHigh {
+                expected: latest_message_id + 1,
+            });
+        }
+        let final_received_message_id = *state
+            .final_received_message_id
+            .get(&final_received_key)
+            .unwrap_or(&0u64);
+        if final_received_message_id == id {
+            return Err(Error::AlreadyReceived);
+        }
+        let received_key = MapKey::MessageId {
+            chain_name: message.from_chain.clone(),
+            id,
+        };
+        // a late vote for a message already agreed on would start it over
+        if is_agreed(&state, &message.from_chain, id) {
+            return Err(Error::AlreadyCompleted);
+        }
+        let pending = PENDING_MESSAGE.with(|pending| pending.borrow().get(&received_key));
+        let mut entry = pending.unwrap_or_else(|| PendingEntry {
+            epoch: *state.validator_epoch.get(&message.from_chain).unwrap_or(&0),
+            validators: state
+                .chain_validators
+                .get(&message.from_chain)
+                .map(|validators| validators.iter().cloned().collect())
+                .unwrap_or_default(),
+            groups: BTreeMap::new(),
         });
+        if entry
+            .groups
+            .values()
+            .any(|group| group.validators.contains(&validator))
+        {
+            return Err(Error::DuplicateVote);
+        }
+
+        if id == latest_message_id + 1 {
+            state
+                .latest_message_id
+                .insert(message.from_chain.clone(), id);
+        }
+        if id > final_received_message_id {
+            state
+                .final_received_message_id
+                .insert(final_received_key, id);
+        }
+        entry
+            .groups
+            .entry(message_hash)
+            .or_insert_with(|| PendingMessage {
+                message: message.clone(),
+                validators: vec![],
+            })
+            .validators
+            .push(validator);
+        if entry.groups.len() > 1 {
+            record_conflict(&mut state, &received_key, &entry);
+        }
+        let agreed = if state.frozen_message.contains(&received_key) {
+            None
+        } else {
+            agreed_message(&state, &message.from_chain, &entry)
+        };
+        if let Some(agreed) = agreed {
+            EXECUTABLE_MESSAGE
+                .with(|executable| executable.borrow_mut().insert(received_key.clone(), agreed));
+            PENDING_MESSAGE.with(|pending| pending.borrow_mut().remove(&received_key));
+            mark_agreed(&mut state, &received_key);
+        } else {
+            PENDING_MESSAGE.with(|pending| pending.borrow_mut().insert(received_key, entry));
+        }
         Ok(true)
-    } else {
-        Err(Error::NotValidator)
-    }
+    })
 }
 
 #[update(name = "sendMessage")]
-fn send_message(to_chain: String, content: Content, session: Session) {
+fn send_message(to_chain: String, mut content: Content, session: Session) {
     let caller = api::caller();
     // let signer = caller.to_text();
     STATE.with(|state| {
         let mut state = state.borrow_mut();
-        let id = state.sent_message_count.get(&to_chain).unwrap_or(&0u64) + 1;
         assert!(state.lockers.contains(&caller), "not register locker");
+        if let Err(err) = check_outbound(&state, &to_chain, &mut content) {
+            api::trap(&err);
+        }
         let message = Message {
-            from_chain: "DFINITY".to_string(),
-            to_chain: to_chain.clone(),
+            from_chain: state.local_chain.clone(),
+            to_chain,
             sender: caller.to_text(),
             signer: caller.to_text(),
             sqos: Sqos { reveal: 1u8 },
             content,
             session,
         };
-        state.sent_message.insert(
+        enqueue_message(&mut state, message);
+    })
+}
+
+// ABI encodes `values` as the arguments of `action` on an EVM contract
+#[update(name = "sendAbiMessage")]
+fn send_abi_message(
+    to_chain: String,
+    contract: String,
+    action: String,
+    values: Vec<abi::AbiValue>,
+    session: Session,
+) {
+    let data = abi::encode(&values).unwrap_or_else(|err| api::trap(&err));
+    let content = Content {
+        contract,
+        action,
+        data: hex::encode(data),
+        encoding: Some(PayloadEncoding::EvmAbi),
+    };
+    send_message(to_chain, content, session)
+}
+
+// Checks `content` against the registry entry of `to_chain`. The chain's
+// encoding is filled in when none is given, stated explicitly for the
+// destination-side relayer.
+fn check_outbound(state: &State, to_chain: &str, content: &mut Content) -> StdResult<(), String> {
+    let config = state
+        .chain_registry
+        .get(to_chain)
+        .ok_or("to chain not register")?;
+    if !config.enabled {
+        return Err("to chain disabled".to_string());
+    }
+    if !config.address_format.is_valid(&content.contract) {
+        return Err("invalid contract address".to_string());
+    }
+    let encoding = *content.encoding.get_or_insert(config.encoding);
+    // NEAR contracts take JSON arguments or raw bytes only
+    if let (
+        ChainFamily::Near,
+        PayloadEncoding::CandidText | PayloadEncoding::CandidBinary | PayloadEncoding::EvmAbi,
+    ) = (&config.family, encoding)
+    {
+        return Err("encoding not supported by the to chain".to_string());
+    }
+    if args::check_payload(&content.data, encoding).is_err() {
+        return Err("data does not match its encoding".to_string());
+    }
+    Ok(())
+}
+
+// queues an outbound message under the next id of its destination chain
+fn enqueue_message(state: &mut State, message: Message) -> u64 {
+    let to_chain = message.to_chain.clone();
+    let id = state.sent_message_count.get(&to_chain).unwrap_or(&0u64) + 1;
+    SENT_MESSAGE.with(|sent| {
+        sent.borrow_mut().insert(
             MapKey::MessageId {
                 chain_name: to_chain.clone(),
                 id,
             },
             message,
-        );
-        state.sent_message_count.insert(to_chain, id);
+        )
+    });
+    state.sent_message_count.insert(to_chain, id);
+    id
+}
+
+// sends the destination canister's reply back to the requester on the source chain
+fn send_response(request: &Message, reply: &[u8]) {
+    let interface = STATE.with(|state| {
+        state
+            .borrow()
+            .contract_interface
+            .get(&request.content.contract)
+            .cloned()
+    });
+    match args::decode_reply(reply, interface.as_deref(), &request.content.action) {
+        Ok(args) => send_reply(request, SESSION_RESPONSE, args),
+        Err(err) => drop_reply(request, &err),
+    }
+}
+
+// tells the sender on the source chain that its message could not be delivered
+fn send_error(request: &Message, reject_code: RejectionCode, reject_message: &str) {
+    let args = IDLArgs {
+        args: vec![
+            IDLValue::Int32(reject_code as i32),
+            IDLValue::Text(reject_message.to_string()),
+        ],
+    };
+    send_reply(request, SESSION_ERROR, args);
+}
+
+// Replies go to the callback the request names, encoded the way its chain
+// takes payloads and checked like any outbound message.
+fn send_reply(request: &Message, res_type: u8, args: IDLArgs) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let content = reply_content(&state, request, &args).and_then(|mut content| {
+            check_outbound(&state, &request.from_chain, &mut content)?;
+            Ok(content)
+        });
+        let content = match content {
+            Ok(content) => content,
+            Err(err) => return drop_reply(request, &err),
+        };
+        let reply = Message {
+            from_chain: state.local_chain.clone(),
+            to_chain: request.from_chain.clone(),
+            sender: request.content.contract.clone(),
+            signer: request.content.contract.clone(),
+            sqos: Sqos { reveal: 1u8 },
+            content,
+            session: Session {
+                res_type,
+                id: request.session.id,
+                callback: None,
+            },
+        };
+        enqueue_message(&mut state, reply);
     })
 }
 
+fn reply_content(state: &State, request: &Message, args: &IDLArgs) -> StdResult<Content, String> {
+    let action = request
+        .session
+        .callback
+        .clone()
+        .ok_or("request names no callback")?;
+    let encoding = state
+        .chain_registry
+        .get(&request.from_chain)
+        .ok_or("to chain not register")?
+        .encoding;
+    Ok(Content {
+        contract: request.sender.clone(),
+        action,
+        data: args::encode_payload(args, encoding)?,
+        encoding: Some(encoding),
+    })
+}
+
+// the destination was already called, so a reply that can't be sent is only logged
+fn drop_reply(request: &Message, err: &str) {
+    api::print(format!(
+        "reply to session {} of {} dropped: {}",
+        request.session.id, request.from_chain, err
+    ));
+}
+
 #[update(name = "executeMessage")]
 async fn execute_message(from_chain: String, id: u64) -> Result {
     let executable_key = MapKey::MessageId {
+        chain_name: from_chain,
+        id,
+    };
+    execute(executable_key, id, api::caller()).await
+}
+
+// Executes up to `max` executable messages of `from_chain` in id order, one
+// after the other. A failed message doesn't stop the following ones.
+#[update(name = "executeMessages")]
+async fn execute_messages(from_chain: String, max: u64) -> Vec<(u64, Result)> {
+    let executor = api::caller();
+    let page = EXECUTABLE_MESSAGE
+        .with(|executable| message_page(&executable.borrow(), from_chain.clone(), 0, max));
+    let mut results = vec![];
+    for (id, _) in page.entries {
+        let executable_key = MapKey::MessageId {
+            chain_name: from_chain.clone(),
+            id,
+        };
+        results.push((id, execute(executable_key, id, executor).await));
+    }
+    results
+}
+
+#[heartbeat]
+fn heartbeat() {
+    let enabled = STATE.with(|state| state.borrow().auto_execute);
+    // a batch may take several rounds, don't start another one meanwhile
+    if enabled && !AUTO_EXECUTING.with(|executing| executing.replace(true)) {
+        ic_cdk::spawn(async {
+            let _guard = AutoExecuting;
+            auto_execute().await;
+        });
+    }
+}
+
+// Clears `AUTO_EXECUTING` when dropped, also when a callback traps and the
+// batch is abandoned, so the heartbeat never stops starting new batches.
+struct AutoExecuting;
+
+impl Drop for AutoExecuting {
+    fn drop(&mut self) {
+        AUTO_EXECUTING.with(|executing| executing.set(false));
+    }
+}
+
+// Walks the executable messages of all chains a batch at a time, so messages
+// that keep failing before delivery can't hold up the others.
+async fn auto_execute() {
+    let batch = STATE.with(|state| match state.borrow().auto_execute_batch {
+        0 => DEFAULT_AUTO_EXECUTE_BATCH,
+        batch => batch,
+    }) as usize;
+    let keys: Vec<MapKey> = EXECUTABLE_MESSAGE.with(|executable| {
+        let executable = executable.borrow();
+        let cursor = AUTO_EXECUTE_CURSOR.with(|cursor| cursor.borrow_mut().take());
+        let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+        executable
+            .range((start, Bound::Unbounded))
+            .take(batch)
+            .map(|(key, _)| key)
+            .collect()
+    });
+    // an empty or short batch reached the end, the next one starts over
+    if keys.len() == batch {
+        AUTO_EXECUTE_CURSOR.with(|cursor| *cursor.borrow_mut() = keys.last().cloned());
+    }
+    for key in keys {
+        if let MapKey::MessageId { id, .. } = key {
+            // failures are kept in the failed messages and receipts
+            let _ = execute(key, id, api::id()).await;
+        }
+    }
+}
+
+// Delivers an executable message on behalf of `executor`, which is the
+// canister itself when the heartbeat executes it.
+async fn execute(executable_key: MapKey, id: u64, executor: Principal) -> Result {
+    let message =
+        match EXECUTABLE_MESSAGE.with(|executable| executable.borrow().get(&executable_key)) {
+            Some(message) => message,
+            None => return Err(Error::MessageNotExists),
+        };
+    let from_chain = message.from_chain.clone();
+    if let Some(expected) = expected_in_order(&from_chain, id) {
+        return Err(Error::OutOfOrder { expected });
+    }
+    let result = deliver_message(&executable_key, id, &message, executor).await?;
+    EXECUTABLE_MESSAGE.with(|executable| {
+        // let message = state.executable_message.get_mut(&executable_key).unwrap();
+        // message.content.data = data;
+        executable.borrow_mut().remove(&executable_key);
+    });
+    let result = match result {
+        Ok(_) => Ok(true),
+        Err((reject_code, reject_message)) => {
+            record_failure(executable_key, message, reject_code, reject_message);
+            Err(Error::ExecuteMessageFailed)
+        }
+    };
+    advance_delivered(&from_chain, id);
+    result
+}
+
+#[update(name = "retryMessage")]
+async fn retry_message(from_chain: String, id: u64) -> Result {
+    let failed_key = MapKey::MessageId {
         chain_name: from_chain.clone(),
         id,
     };
-    let message = STATE.with(|state| {
-        let state = state.borrow();
-        state
-            .executable_message
-            .get(&executable_key)
-            .expect("not exists")
-            .clone()
+    let failed = match FAILED_MESSAGE.with(|failed| failed.borrow().get(&failed_key)) {
+        Some(failed) => failed,
+        None => return Err(Error::MessageNotExists),
+    };
+    if let FailedStatus::DeadLetter = failed.status {
+        return Err(Error::NotRetryable);
+    }
+    if let Some(expected) = expected_in_order(&from_chain, id) {
+        return Err(Error::OutOfOrder { expected });
+    }
+    let result = deliver_message(&failed_key, id, &failed.message, api::caller()).await?;
+    let result = match result {
+        Ok(_) => {
+            FAILED_MESSAGE.with(|failed| failed.borrow_mut().remove(&failed_key));
+            Ok(true)
+        }
+        Err((reject_code, reject_message)) => {
+            record_failure(failed_key, failed.message, reject_code, reject_message);
+            Err(Error::ExecuteMessageFailed)
+        }
+    };
+    advance_delivered(&from_chain, id);
+    result
+}
+
+// With ordered delivery, message `id` waits until every message before it was
+// delivered or given up on. Returns the message that has to go first.
+fn expected_in_order(from_chain: &str, id: u64) -> Option<u64> {
+    STATE
+        .with(|state| delivered_message_id(&state.borrow(), from_chain))
+        .map(|delivered| delivered + 1)
+        .filter(|expected| id > *expected)
+}
+
+fn delivered_message_id(state: &State, from_chain: &str) -> Option<u64> {
+    let ordered = state
+        .chain_registry
+        .get(from_chain)
+        .is_some_and(|config| config.ordered_delivery);
+    if !ordered {
+        return None;
+    }
+    match state.delivered_message_id.get(from_chain) {
+        Some(delivered) => Some(*delivered),
+        None => Some(first_undelivered(state, from_chain, 0).saturating_sub(1)),
+    }
+}
+
+// Once message `id` is done, moves past it and the messages after it that
+// were already done, e.g. executed before ordered delivery was turned on.
+// Messages at or below the mark are never waited for again.
+fn advance_delivered(from_chain: &str, id: u64) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if let Some(delivered) =
+            delivered_message_id(&state, from_chain).filter(|delivered| id == delivered + 1)
+        {
+            let delivered = first_undelivered(&state, from_chain, delivered).saturating_sub(1);
+            state
+                .delivered_message_id
+                .insert(from_chain.to_string(), delivered);
+        }
+    })
+}
+
+// Starts tracking from the chain's first undelivered message when ordered
+// delivery is turned on, and stops when it is turned off.
+fn track_delivered(state: &mut State, chain_name: &str) {
+    match delivered_message_id(state, chain_name) {
+        Some(delivered) => {
+            state
+                .delivered_message_id
+                .insert(chain_name.to_string(), delivered);
+        }
+        None => {
+            state.delivered_message_id.remove(chain_name);
+        }
+    }
+}
+
+// First message from `chain_name` after `after` still waiting for votes,
+// execution or a retry, the one after the latest received when there is none.
+fn first_undelivered(state: &State, chain_name: &str, after: u64) -> u64 {
+    let range = MapKey::MessageId {
+        chain_name: chain_name.to_string(),
+        id: after + 1,
+    }..=MapKey::MessageId {
+        chain_name: chain_name.to_string(),
+        id: u64::MAX,
+    };
+    let pending = PENDING_MESSAGE.with(|pending| {
+        pending
+            .borrow()
+            .range(range.clone())
+            .next()
+            .map(|(key, _)| key)
+    });
+    let executable = EXECUTABLE_MESSAGE.with(|executable| {
+        executable
+            .borrow()
+            .range(range.clone())
+            .next()
+            .map(|(key, _)| key)
     });
+    let retryable = FAILED_MESSAGE.with(|failed| {
+        failed
+            .borrow()
+            .range(range.clone())
+            .find(|(_, failed)| matches!(failed.status, FailedStatus::Retryable))
+            .map(|(key, _)| key)
+    });
+    vec![pending, executable, retryable]
+        .into_iter()
+        .filter_map(|key| match key {
+            Some(MapKey::MessageId { id, .. }) => Some(id),
+            _ => None,
+        })
+        .min()
+        .unwrap_or_else(|| state.latest_message_id.get(chain_name).unwrap_or(&0) + 1)
+        .max(after + 1)
+}
+
+// Calls the destination canister. The message is claimed for the duration of
+// the call so a concurrent execute or retry can't deliver it twice.
+async fn deliver_message(
+    key: &MapKey,
+    id: u64,
+    message: &Message,
+    executor: Principal,
+) -> Result<CallResult<Vec<u8>>> {
+    // messages received before the contract was checked on receive may not
+    // name a canister, fail them instead of trapping
+    let contract = match Principal::from_text(&message.content.contract) {
+        Ok(contract) => contract,
+        Err(err) => {
+            let result = Err((
+                RejectionCode::DestinationInvalid,
+                format!("invalid contract: {}", err),
+            ));
+            record_receipt(key.clone(), &result, executor);
+            return Ok(result);
+        }
+    };
     let context = get_context(id, message.clone());
-    let mut data: IDLArgs = message.content.data.parse().unwrap();
-    // TODO
-    // direct use args catch "collision or not sorted" error, so parse again.
-    data.args.push(context);
-    let args: IDLArgs = format!("{:?}", data).parse().unwrap();
-    // api::print(format!("{:?}", args.clone()));
+    let interface = STATE.with(|state| {
+        state
+            .borrow()
+            .contract_interface
+            .get(&message.content.contract)
+            .cloned()
+    });
+    let args = match args::encode_call_args(
+        &message.content.data,
+        message
+            .content
+            .encoding
+            .unwrap_or(PayloadEncoding::CandidText),
+        context,
+        interface.as_deref(),
+        &message.content.action,
+    ) {
+        Ok(args) => args,
+        // the call is never made, but it counts as a failed attempt so the
+        // message can still be dead-lettered and reported
+        Err(err) => {
+            let result = Err((
+                RejectionCode::CanisterReject,
+                format!("encode args failed: {}", err),
+            ));
+            record_receipt(key.clone(), &result, executor);
+            return Ok(result);
+        }
+    };
+    let claimed = STATE.with(|state| state.borrow_mut().executing_message.insert(key.clone()));
+    if !claimed {
+        return Err(Error::MessageExecuting);
+    }
     let result = api::call::call_raw(
-        Principal::from_text(message.content.contract.clone()).unwrap(),
+        contract,
         message.content.action.as_str(),
-        args.to_bytes().unwrap().as_slice(),
+        args.as_slice(),
         0,
     )
     .await;
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        // let message = state.executable_message.get_mut(&executable_key).unwrap();
-        // message.content.data = data;
-        state.executable_message.remove(&executable_key);
-    });
-    match result {
-        Ok(_) => Ok(true),
-        Err(_) => Err(Error::ExecuteMessageFailed),
+    STATE.with(|state| state.borrow_mut().executing_message.remove(key));
+    record_receipt(key.clone(), &result, executor);
+    if let Ok(reply) = &result {
+        if message.session.res_type == SESSION_REQUEST {
+            send_response(message, reply);
+        }
+    }
+    Ok(result)
+}
+
+// the latest attempt wins, so a successful retry replaces the failed receipt
+fn record_receipt(key: MapKey, result: &CallResult<Vec<u8>>, executor: Principal) {
+    let receipt = match result {
+        Ok(reply) => ExecutionReceipt {
+            status: ExecutionStatus::Succeeded,
+            reject_code: None,
+            reject_message: None,
+            reply_text: IDLArgs::from_bytes(reply).ok().map(|args| args.to_string()),
+            reply: reply.clone(),
+            executor,
+            timestamp: api::time(),
+        },
+        Err((reject_code, reject_message)) => ExecutionReceipt {
+            status: ExecutionStatus::Failed,
+            reject_code: Some(*reject_code),
+            reject_message: Some(reject_message.clone()),
+            reply: vec![],
+            reply_text: None,
+            executor,
+            timestamp: api::time(),
+        },
+    };
+    EXECUTED_MESSAGE.with(|executed| executed.borrow_mut().insert(key, receipt));
+}
+
+fn record_failure(
+    key: MapKey,
+    message: Message,
+    reject_code: RejectionCode,
+    reject_message: String,
+) {
+    let max_attempts = STATE.with(|state| max_execution_attempts(&state.borrow()));
+    FAILED_MESSAGE.with(|failed| {
+        let mut failed = failed.borrow_mut();
+        let attempts = failed.get(&key).map_or(0, |failed| failed.attempts) + 1;
+        // report the first failure so the sender isn't left waiting on retries,
+        // a request that later succeeds still gets its response
+        if attempts == 1 {
+            if let SESSION_REQUEST | SESSION_ERROR_CALLBACK = message.session.res_type {
+                send_error(&message, reject_code, &reject_message);
+            }
+        }
+        let status = if attempts >= max_attempts {
+            FailedStatus::DeadLetter
+        } else {
+            FailedStatus::Retryable
+        };
+        failed.insert(
+            key,
+            FailedMessage {
+                message,
+                attempts,
+                reject_code,
+                reject_message,
+                status,
+            },
+        );
+    })
+}
+
+fn max_execution_attempts(state: &State) -> u32 {
+    match state.max_execution_attempts {
+        0 => DEFAULT_MAX_EXECUTION_ATTEMPTS,
+        attempts => attempts,
     }
 }
 
@@ -284,64 +1203,121 @@ fn get_context(id: u64, message: Message) -> IDLValue {
         },
         IDLField {
             id: Label::Named("session".to_string()),
-            val: IDLValue::Record(session),
+            val: args::record(session),
         },
     ];
-    IDLValue::Record(idl_field)
+    args::record(idl_field)
 }
 
 #[query(name = "getPendingMessage")]
 fn get_pending_message() -> Vec<(MapKey, Vec<(String, PendingMessage)>)> {
-    STATE.with(|state| {
-        state
+    PENDING_MESSAGE.with(|pending| {
+        pending
             .borrow()
-            .pending_message
-            .clone()
-            .into_iter()
-            .map(|(key, value)| {
-                (
-                    key,
-                    value
-                        .into_iter()
-                        .map(|(msg_hash, group)| (msg_hash, group))
-                        .collect(),
-                )
-            })
+            .iter()
+            .map(|(key, entry)| (key, entry.groups.into_iter().collect()))
             .collect()
     })
 }
 
+#[query(name = "getPendingMessagePage")]
+fn get_pending_message_page(
+    chain_name: String,
+    start_id: u64,
+    limit: u64,
+) -> MessagePage<Vec<(String, PendingMessage)>> {
+    let page = PENDING_MESSAGE
+        .with(|pending| message_page(&pending.borrow(), chain_name, start_id, limit));
+    MessagePage {
+        entries: page
+            .entries
+            .into_iter()
+            .map(|(id, entry)| (id, entry.groups.into_iter().collect()))
+            .collect(),
+        next_id: page.next_id,
+    }
+}
+
 #[query(name = "getExecutableMessage")]
 fn get_executable_message() -> Vec<(MapKey, Message)> {
+    EXECUTABLE_MESSAGE.with(|executable| executable.borrow().iter().collect())
+}
+
+#[query(name = "getExecutableMessagePage")]
+fn get_executable_message_page(
+    chain_name: String,
+    start_id: u64,
+    limit: u64,
+) -> MessagePage<Message> {
+    EXECUTABLE_MESSAGE
+        .with(|executable| message_page(&executable.borrow(), chain_name, start_id, limit))
+}
+
+#[query(name = "getExecutingMessage")]
+fn get_executing_message() -> Vec<MapKey> {
+    STATE.with(|state| state.borrow().executing_message.iter().cloned().collect())
+}
+
+#[query(name = "getFailedMessages")]
+fn get_failed_messages() -> Vec<(MapKey, FailedMessage)> {
+    FAILED_MESSAGE.with(|failed| failed.borrow().iter().collect())
+}
+
+#[query(name = "getAutoExecute")]
+fn get_auto_execute() -> (bool, u64) {
     STATE.with(|state| {
-        state
-            .borrow()
-            .executable_message
-            .clone()
-            .into_iter()
-            .map(|map| map)
-            .collect()
+        let state = state.borrow();
+        (state.auto_execute, state.auto_execute_batch)
     })
 }
 
+#[query(name = "getMaxExecutionAttempts")]
+fn get_max_execution_attempts() -> u32 {
+    STATE.with(|state| max_execution_attempts(&state.borrow()))
+}
+
+#[query(name = "getExecutionReceipt")]
+fn get_execution_receipt(chain_name: String, id: u64) -> Option<ExecutionReceipt> {
+    EXECUTED_MESSAGE.with(|executed| executed.borrow().get(&MapKey::MessageId { chain_name, id }))
+}
+
+#[query(name = "getInterface")]
+fn get_interface(contract: String) -> Option<String> {
+    STATE.with(|state| state.borrow().contract_interface.get(&contract).cloned())
+}
+
 #[query(name = "getSentMessage")]
 fn get_sent_message() -> Vec<(MapKey, Message)> {
-    STATE.with(|state| {
-        state
-            .borrow()
-            .sent_message
-            .clone()
+    SENT_MESSAGE.with(|sent| sent.borrow().iter().collect())
+}
+
+#[query(name = "getSentMessagePage")]
+fn get_sent_message_page(chain_name: String, start_id: u64, limit: u64) -> MessagePage<Message> {
+    SENT_MESSAGE.with(|sent| message_page(&sent.borrow(), chain_name, start_id, limit))
+}
+
+// up to `max` consecutive messages to `to_chain` from `from_id` on, so a relayer
+// catches up on a backlog without a call per message
+#[query(name = "getSentMessagesFrom")]
+fn get_sent_messages_from(to_chain: String, from_id: u64, max: u64) -> Vec<SentMessage> {
+    SENT_MESSAGE.with(|sent| {
+        message_page(&sent.borrow(), to_chain, from_id, max)
+            .entries
             .into_iter()
-            .map(|result| result)
+            .map(|(id, message)| SentMessage {
+                id,
+                hash: message.to_hash(),
+                message,
+            })
             .collect()
     })
 }
 
 #[query(name = "getSentMessageById")]
 fn get_sent_message_by_id(chain_name: String, id: u64) -> Message {
-    STATE.with(|state| {
+    SENT_MESSAGE.with(|sent| {
         let key = MapKey::MessageId { chain_name, id };
-        state.borrow().sent_message.get(&key).cloned().unwrap()
+        sent.borrow().get(&key).unwrap()
     })
 }
 
@@ -372,41 +1348,109 @@ fn get_final_received_message_id(chain_name: String, validator: Principal) -> u6
 
 #[query(name = "getMsgPortingTask")]
 fn get_msg_porting_task(from_chain: String, validator: Principal) -> u64 {
+    assert!(
+        is_validator(&validator, &from_chain),
+        "{} is not a validator of {}",
+        validator.to_text(),
+        from_chain
+    );
     STATE.with(|state| {
         let state = state.borrow();
-        let len = state.pending_message.len();
-        let final_received_message_id = state
+        let final_received_message_id = *state
             .final_received_message_id
             .get(&MapKey::ValidatorFinalReceivedId {
                 chain_name: from_chain.clone(),
                 validator,
             })
             .unwrap_or(&0);
-        if len != 0 {
-            for (key, _) in state.pending_message.clone() {
-                match key {
-                    MapKey::MessageId { chain_name, id } => {
-                        if chain_name != from_chain || id <= *final_received_message_id {
-                            continue;
-                        }
-                        return id;
-                    }
-                    _ => {}
-                }
-            }
+        // first pending message of the chain this validator has not ported yet
+        let pending = PENDING_MESSAGE.with(|pending| {
+            pending
+                .borrow()
+                .range(
+                    MapKey::MessageId {
+                        chain_name: from_chain.clone(),
+                        id: final_received_message_id.saturating_add(1),
+                    }..=MapKey::MessageId {
+                        chain_name: from_chain.clone(),
+                        id: u64::MAX,
+                    },
+                )
+                .next()
+        });
+        if let Some((MapKey::MessageId { id, .. }, _)) = pending {
+            return id;
         }
         state.latest_message_id.get(&from_chain).unwrap_or(&0) + 1
     })
 }
 
-#[query(name = "getLatestMessageId")]
-fn get_latest_message_id(chain_name: String) -> u64 {
+#[query(name = "getLocalChain")]
+fn get_local_chain() -> String {
+    STATE.with(|state| state.borrow().local_chain.clone())
+}
+
+#[query(name = "getLatestMessageId")]
+fn get_latest_message_id(chain_name: String) -> u64 {
+    STATE.with(|state| {
+        *state
+            .borrow()
+            .latest_message_id
+            .get(&chain_name)
+            .unwrap_or(&0u64)
+    })
+}
+
+#[query(name = "getValidatorEpoch")]
+fn get_validator_epoch(chain_name: String) -> u64 {
+    STATE.with(|state| {
+        *state
+            .borrow()
+            .validator_epoch
+            .get(&chain_name)
+            .unwrap_or(&0u64)
+    })
+}
+
+#[query(name = "getPendingMessageEpochs")]
+fn get_pending_message_epochs() -> Vec<(MapKey, u64)> {
+    PENDING_MESSAGE.with(|pending| {
+        pending
+            .borrow()
+            .iter()
+            .map(|(key, entry)| (key, entry.epoch))
+            .collect()
+    })
+}
+
+#[query(name = "getConflicts")]
+fn get_conflicts() -> Vec<ConflictEvidence> {
+    STATE.with(|state| state.borrow().conflicts.values().cloned().collect())
+}
+
+#[query(name = "getFrozenMessages")]
+fn get_frozen_messages() -> Vec<MapKey> {
+    STATE.with(|state| state.borrow().frozen_message.iter().cloned().collect())
+}
+
+#[query(name = "getFreezeOnConflict")]
+fn get_freeze_on_conflict() -> bool {
+    STATE.with(|state| state.borrow().freeze_on_conflict)
+}
+
+#[query(name = "getQuorumThreshold")]
+fn get_quorum_threshold() -> u64 {
+    STATE.with(|state| state.borrow().quorum_threshold)
+}
+
+#[query(name = "getChainQuorum")]
+fn get_chain_quorum(chain_name: String) -> Option<Quorum> {
     STATE.with(|state| {
-        *state
+        state
             .borrow()
-            .latest_message_id
+            .chain_registry
             .get(&chain_name)
-            .unwrap_or(&0u64)
+            .and_then(|config| config.quorum.clone())
     })
 }
 
@@ -436,26 +1480,221 @@ fn get_custodians() -> Vec<Principal> {
     })
 }
 
-#[query(name = "getValidators")]
-fn get_validators() -> Vec<Principal> {
+#[query(name = "getChains")]
+fn get_chains() -> Vec<(String, ChainConfig)> {
     STATE.with(|state| {
         state
             .borrow()
-            .validators
-            .clone()
-            .into_iter()
-            .map(|validator| validator)
+            .chain_registry
+            .iter()
+            .map(|(chain_name, config)| (chain_name.clone(), config.clone()))
             .collect()
     })
 }
 
-fn is_validator(principal: &Principal) -> bool {
+#[query(name = "getValidators")]
+fn get_validators(chain_name: String) -> Vec<Principal> {
     STATE.with(|state| {
-        if state.borrow().validators.contains(principal) {
-            true
-        } else {
-            false
+        state
+            .borrow()
+            .chain_validators
+            .get(&chain_name)
+            .map(|validators| validators.iter().cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+// Messages of `chain_name` in id order from `start_id` on. `next_id` is where
+// the following page starts, none once the chain's messages are exhausted.
+fn message_page<V: Storable>(
+    map: &StableBTreeMap<MapKey, V, Memory>,
+    chain_name: String,
+    start_id: u64,
+    limit: u64,
+) -> MessagePage<V> {
+    let limit = match limit {
+        0 => MAX_PAGE_SIZE,
+        limit => limit.min(MAX_PAGE_SIZE),
+    } as usize;
+    let mut entries: Vec<(u64, V)> = map
+        .range(
+            MapKey::MessageId {
+                chain_name: chain_name.clone(),
+                id: start_id,
+            }..=MapKey::MessageId {
+                chain_name,
+                id: u64::MAX,
+            },
+        )
+        .take(limit + 1)
+        .filter_map(|(key, value)| match key {
+            MapKey::MessageId { id, .. } => Some((id, value)),
+            _ => None,
+        })
+        .collect();
+    let next_id = if entries.len() > limit {
+        entries.pop().map(|(id, _)| id)
+    } else {
+        None
+    };
+    MessagePage { entries, next_id }
+}
+
+fn required_votes(state: &State, from_chain: &str, validators: usize) -> usize {
+    let quorum = state
+        .chain_registry
+        .get(from_chain)
+        .and_then(|config| config.quorum.as_ref());
+    match quorum {
+        Some(Quorum::Count(count)) => *count as usize,
+        // round up so that e.g. 67% of 3 validators still needs 3 votes
+        Some(Quorum::Percentage(percentage)) => (validators * *percentage as usize).div_ceil(100),
+        None => match state.quorum_threshold {
+            0 => validators,
+            threshold => threshold as usize,
+        },
+    }
+}
+
+// Votes on a pending message count only for validators that were registered
+// when it started and are still registered, so removing a validator shrinks
+// both the votes and the quorum base while later additions don't affect it.
+fn eligible_validators(
+    state: &State,
+    chain_name: &str,
+    entry: &PendingEntry,
+) -> HashSet<Principal> {
+    let current = state
+        .chain_validators
+        .get(chain_name)
+        .cloned()
+        .unwrap_or_default();
+    entry
+        .validators
+        .iter()
+        .filter(|validator| current.contains(validator))
+        .cloned()
+        .collect()
+}
+
+fn agreed_message(state: &State, chain_name: &str, entry: &PendingEntry) -> Option<Message> {
+    let eligible = eligible_validators(state, chain_name, entry);
+    if eligible.is_empty() {
+        return None;
+    }
+    let required_votes = required_votes(state, chain_name, eligible.len());
+    entry
+        .groups
+        .values()
+        .find(|group| {
+            group
+                .validators
+                .iter()
+                .filter(|validator| eligible.contains(validator))
+                .count()
+                >= required_votes
+        })
+        .map(|group| group.message.clone())
+}
+
+fn promote_agreed_messages(state: &mut State, chain_name: &str) {
+    let agreed: Vec<(MapKey, Message)> = PENDING_MESSAGE.with(|pending| {
+        pending
+            .borrow()
+            .range(
+                MapKey::MessageId {
+                    chain_name: chain_name.to_string(),
+                    id: 0,
+                }..=MapKey::MessageId {
+                    chain_name: chain_name.to_string(),
+                    id: u64::MAX,
+                },
+            )
+            .filter(|(key, _)| !state.frozen_message.contains(key))
+            .filter_map(|(key, entry)| {
+                agreed_message(state, chain_name, &entry).map(|message| (key, message))
+            })
+            .collect()
+    });
+    for (key, message) in agreed {
+        PENDING_MESSAGE.with(|pending| pending.borrow_mut().remove(&key));
+        EXECUTABLE_MESSAGE.with(|executable| executable.borrow_mut().insert(key.clone(), message));
+        mark_agreed(state, &key);
+    }
+}
+
+// Messages can be agreed on out of id order, those past the first gap are kept
+// apart until it closes.
+fn mark_agreed(state: &mut State, key: &MapKey) {
+    if let MapKey::MessageId { chain_name, id } = key {
+        let agreed_message_id = state
+            .agreed_message_id
+            .entry(chain_name.clone())
+            .or_default();
+        if *id <= *agreed_message_id {
+            return;
+        }
+        state.agreed_message.insert(key.clone());
+        while state.agreed_message.remove(&MapKey::MessageId {
+            chain_name: chain_name.clone(),
+            id: *agreed_message_id + 1,
+        }) {
+            *agreed_message_id += 1;
+        }
+    }
+}
+
+fn is_agreed(state: &State, chain_name: &str, id: u64) -> bool {
+    id <= *state.agreed_message_id.get(chain_name).unwrap_or(&0)
+        || state.agreed_message.contains(&MapKey::MessageId {
+            chain_name: chain_name.to_string(),
+            id,
+        })
+}
+
+fn record_conflict(state: &mut State, key: &MapKey, entry: &PendingEntry) {
+    let votes = entry
+        .groups
+        .iter()
+        .map(|(message_hash, group)| (message_hash.clone(), group.validators.clone()))
+        .collect();
+    if let Some(evidence) = state.conflicts.get_mut(key) {
+        evidence.votes = votes;
+        return;
+    }
+    if let MapKey::MessageId { chain_name, id } = key {
+        state.conflicts.insert(
+            key.clone(),
+            ConflictEvidence {
+                chain_name: chain_name.clone(),
+                id: *id,
+                votes,
+                detected_at: now(),
+                resolved: false,
+            },
+        );
+        if state.freeze_on_conflict {
+            state.frozen_message.insert(key.clone());
         }
+    }
+}
+
+// `api::time` traps outside a canister, unit tests get 0
+fn now() -> u64 {
+    if cfg!(target_arch = "wasm32") {
+        api::time()
+    } else {
+        0
+    }
+}
+
+fn is_validator(principal: &Principal, chain_name: &str) -> bool {
+    STATE.with(|state| {
+        state
+            .borrow()
+            .chain_validators
+            .get(chain_name)
+            .is_some_and(|validators| validators.contains(principal))
     })
 }
 
@@ -466,9 +1705,155 @@ enum Error {
     AlreadyRegisterLocker,
     AlreadyRegisterValidator,
     ExecuteMessageFailed,
+    InvalidQuorum,
+    ConflictNotFound,
+    IdTooHigh { expected: u64 },
+    AlreadyReceived,
+    AlreadyCompleted,
+    DuplicateVote,
+    MessageNotExists,
+    MessageExecuting,
+    NotRetryable,
+    InvalidInterface,
+    InvalidToChain,
+    UnknownFromChain,
+    ChainNotRegistered,
+    ChainDisabled,
+    InvalidAddress,
+    InvalidContract,
+    OutOfOrder { expected: u64 },
+    BatchTooLarge { max: u64 },
+    Other,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+enum Quorum {
+    // M of N validators
+    Count(u64),
+    // share of the registered validators, 1 to 100
+    Percentage(u8),
+}
+
+impl Quorum {
+    fn is_valid(&self) -> bool {
+        match self {
+            Quorum::Count(count) => *count > 0,
+            Quorum::Percentage(percentage) => (1..=100).contains(percentage),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct ChainConfig {
+    family: ChainFamily,
+    // of contracts on the chain, checked on outbound and inbound messages
+    address_format: AddressFormat,
+    enabled: bool,
+    // overrides `quorum_threshold` for messages from the chain
+    quorum: Option<Quorum>,
+    // of outbound messages that don't state one
+    encoding: PayloadEncoding,
+    // inbound messages execute strictly in id order
+    ordered_delivery: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+enum ChainFamily {
+    Icp,
+    Evm,
+    Near,
     Other,
 }
 
+#[derive(CandidType, Deserialize, Clone)]
+enum AddressFormat {
+    Principal,
+    // 0x followed by 20 hex encoded bytes
+    Evm,
+    // named or implicit NEAR account id
+    NearAccount,
+    Any,
+}
+
+impl AddressFormat {
+    fn is_valid(&self, address: &str) -> bool {
+        match self {
+            AddressFormat::Principal => Principal::from_text(address).is_ok(),
+            AddressFormat::Evm => address
+                .strip_prefix("0x")
+                .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit())),
+            // https://nomicon.io/DataStructures/Account#account-id-rules
+            AddressFormat::NearAccount => {
+                (2..=64).contains(&address.len())
+                    && address.split('.').all(|part| {
+                        part.split(['-', '_']).all(|word| {
+                            !word.is_empty()
+                                && word
+                                    .chars()
+                                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+                        })
+                    })
+            }
+            AddressFormat::Any => true,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct PendingEntry {
+    // validator epoch of the source chain when the first vote arrived
+    epoch: u64,
+    // validator set at that epoch
+    validators: Vec<Principal>,
+    groups: BTreeMap<String, PendingMessage>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+enum ExecutionStatus {
+    Succeeded,
+    Failed,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct ExecutionReceipt {
+    status: ExecutionStatus,
+    reject_code: Option<RejectionCode>,
+    reject_message: Option<String>,
+    // raw Candid reply of the destination canister
+    reply: Vec<u8>,
+    // the reply as Candid text, if it decodes
+    reply_text: Option<String>,
+    executor: Principal,
+    timestamp: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+enum FailedStatus {
+    Retryable,
+    // out of attempts, kept for inspection only
+    DeadLetter,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct FailedMessage {
+    message: Message,
+    attempts: u32,
+    // rejection of the latest attempt
+    reject_code: RejectionCode,
+    reject_message: String,
+    status: FailedStatus,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct ConflictEvidence {
+    chain_name: String,
+    id: u64,
+    // validators that voted for each message hash
+    votes: Vec<(String, Vec<Principal>)>,
+    detected_at: u64,
+    resolved: bool,
+}
+
 #[derive(CandidType, Deserialize, Clone)]
 struct PendingMessage {
     message: Message,
@@ -513,12 +1898,31 @@ struct Content {
     contract: String,
     action: String,
     data: String,
+    // how `data` is encoded, Candid text when absent. Left out of the hash
+    // when absent, so messages without it keep the hash they had before.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<PayloadEncoding>,
+}
+
+// binary encodings carry hex in `Content.data`
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy)]
+enum PayloadEncoding {
+    CandidText,
+    CandidBinary,
+    Json,
+    Raw,
+    // Solidity ABI without the function selector
+    EvmAbi,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone)]
 struct Session {
     res_type: u8,
     id: u64,
+    // method on the sender's chain that takes the response or error, left out
+    // of the hash when absent like `Content.encoding`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    callback: Option<String>,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone)]
@@ -550,6 +1954,26 @@ enum MapKey {
     },
 }
 
+#[derive(CandidType, Deserialize)]
+struct ReceivedMessage {
+    id: u64,
+    message: Message,
+}
+
+#[derive(CandidType, Deserialize)]
+struct SentMessage {
+    id: u64,
+    message: Message,
+    // `Message::to_hash`, what validators vote on
+    hash: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct MessagePage<T> {
+    entries: Vec<(u64, T)>,
+    next_id: Option<u64>,
+}
+
 type Result<T = bool, E = Error> = StdResult<T, E>;
 
 // for debug
@@ -559,16 +1983,18 @@ fn clear_received_message(chains: Vec<String>) -> Result {
         let mut state = state.borrow_mut();
         let caller = api::caller();
         if state.custodians.contains(&caller) {
-            state.executable_message.clear();
-            state.pending_message.clear();
-            let validators: Vec<Principal> = state
-                .validators
-                .clone()
-                .into_iter()
-                .map(|validator| validator)
-                .collect();
+            EXECUTABLE_MESSAGE.with(|executable| executable.borrow_mut().clear_new());
+            PENDING_MESSAGE.with(|pending| pending.borrow_mut().clear_new());
+            FAILED_MESSAGE.with(|failed| failed.borrow_mut().clear_new());
+            EXECUTED_MESSAGE.with(|executed| executed.borrow_mut().clear_new());
+            state.frozen_message.clear();
             for chain_name in chains {
-                for validator in validators.clone() {
+                let validators: Vec<Principal> = state
+                    .chain_validators
+                    .get(&chain_name)
+                    .map(|validators| validators.iter().cloned().collect())
+                    .unwrap_or_default();
+                for validator in validators {
                     state
                         .final_received_message_id
                         .remove(&MapKey::ValidatorFinalReceivedId {
@@ -577,6 +2003,20 @@ fn clear_received_message(chains: Vec<String>) -> Result {
                         });
                 }
                 state.latest_message_id.remove(&chain_name);
+                state.agreed_message_id.remove(&chain_name);
+                state.agreed_message.retain(|key| match key {
+                    MapKey::MessageId {
+                        chain_name: agreed_chain,
+                        ..
+                    } => *agreed_chain != chain_name,
+                    _ => true,
+                });
+            }
+            // nothing is left to wait for, cleared chains start over from their first message
+            let ordered: Vec<String> = state.delivered_message_id.keys().cloned().collect();
+            for chain_name in ordered {
+                let delivered = first_undelivered(&state, &chain_name, 0).saturating_sub(1);
+                state.delivered_message_id.insert(chain_name, delivered);
             }
             Ok(true)
         } else {
@@ -597,9 +2037,7 @@ fn clear_sent_message(chains: Vec<String>) -> Result {
                         chain_name: chain_name.clone(),
                         id,
                     };
-                    if state.sent_message.contains_key(&key) {
-                        state.sent_message.remove(&key);
-                    }
+                    SENT_MESSAGE.with(|sent| sent.borrow_mut().remove(&key));
                 }
                 state.sent_message_count.remove(&chain_name);
             }
@@ -609,3 +2047,545 @@ fn clear_sent_message(chains: Vec<String>) -> Result {
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved_state(state: &State) -> Vec<u8> {
+        candid::utils::encode_args((STATE_VERSION, state)).unwrap()
+    }
+
+    #[test]
+    fn restores_saved_state() {
+        let custodian = Principal::from_text("aaaaa-aa").unwrap();
+        let state = State {
+            custodians: HashSet::from([custodian]),
+            sent_message_count: HashMap::from([("NEAR".to_string(), 7)]),
+            local_chain: "DFINITY".to_string(),
+            max_execution_attempts: 5,
+            ..Default::default()
+        };
+        let restored = restore_state(&saved_state(&state));
+        assert!(restored.custodians.contains(&custodian));
+        assert_eq!(restored.sent_message_count.get("NEAR"), Some(&7));
+        assert_eq!(restored.local_chain, "DFINITY");
+        assert_eq!(restored.max_execution_attempts, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported state version")]
+    fn rejects_unknown_state_version() {
+        let bytes = candid::utils::encode_args((STATE_VERSION + 1, &State::default())).unwrap();
+        restore_state(&bytes);
+    }
+
+    fn message_from(chain_name: &str) -> Message {
+        Message {
+            from_chain: chain_name.to_string(),
+            to_chain: "DFINITY".to_string(),
+            sender: "sender.near".to_string(),
+            signer: "signer.near".to_string(),
+            sqos: Sqos { reveal: 1 },
+            content: Content {
+                contract: "aaaaa-aa".to_string(),
+                action: "receiveGreeting".to_string(),
+                data: "(\"NEAR\")".to_string(),
+                encoding: None,
+            },
+            session: Session {
+                res_type: 0,
+                id: 0,
+                callback: None,
+            },
+        }
+    }
+
+    fn validator(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    // NEAR with validators 1 to `validators`, messages go to "DFINITY"
+    fn register_near(validators: u8, quorum: Option<Quorum>) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.local_chain = "DFINITY".to_string();
+            state.chain_validators.insert(
+                "NEAR".to_string(),
+                (1..=validators).map(validator).collect(),
+            );
+            state.chain_registry.insert(
+                "NEAR".to_string(),
+                ChainConfig {
+                    family: ChainFamily::Near,
+                    address_format: AddressFormat::NearAccount,
+                    enabled: true,
+                    quorum,
+                    encoding: PayloadEncoding::Json,
+                    ordered_delivery: false,
+                },
+            );
+        })
+    }
+
+    fn near_key(id: u64) -> MapKey {
+        MapKey::MessageId {
+            chain_name: "NEAR".to_string(),
+            id,
+        }
+    }
+
+    fn is_pending(id: u64) -> bool {
+        PENDING_MESSAGE.with(|pending| pending.borrow().get(&near_key(id)).is_some())
+    }
+
+    fn is_executable(id: u64) -> bool {
+        EXECUTABLE_MESSAGE.with(|executable| executable.borrow().get(&near_key(id)).is_some())
+    }
+
+    fn vote(n: u8, id: u64, message: &Message) -> Result {
+        receive(validator(n), id, message.clone())
+    }
+
+    fn other_message() -> Message {
+        let mut message = message_from("NEAR");
+        message.content.data = "(\"ETH\")".to_string();
+        message
+    }
+
+    #[test]
+    fn executable_once_every_validator_agrees() {
+        register_near(3, None);
+        let message = message_from("NEAR");
+        assert!(vote(1, 1, &message).is_ok());
+        assert!(vote(2, 1, &message).is_ok());
+        assert!(is_pending(1) && !is_executable(1));
+        assert!(vote(3, 1, &message).is_ok());
+        assert!(!is_pending(1) && is_executable(1));
+    }
+
+    #[test]
+    fn split_vote_waits_for_a_quorum_on_one_hash() {
+        register_near(3, Some(Quorum::Count(2)));
+        let message = message_from("NEAR");
+        assert!(vote(1, 1, &message).is_ok());
+        assert!(vote(2, 1, &other_message()).is_ok());
+        assert!(is_pending(1) && !is_executable(1));
+        assert!(vote(3, 1, &message).is_ok());
+        let executable =
+            EXECUTABLE_MESSAGE.with(|executable| executable.borrow().get(&near_key(1)));
+        assert_eq!(executable.unwrap().to_hash(), message.to_hash());
+    }
+
+    #[test]
+    fn late_votes_after_quorum_are_rejected() {
+        register_near(4, Some(Quorum::Count(2)));
+        let message = message_from("NEAR");
+        assert!(vote(3, 1, &message).is_ok());
+        assert!(vote(4, 1, &message).is_ok());
+        assert!(vote(1, 2, &message).is_ok());
+        assert!(vote(2, 2, &message).is_ok());
+        assert!(is_executable(1) && is_executable(2));
+        // delivered meanwhile
+        EXECUTABLE_MESSAGE.with(|executable| executable.borrow_mut().remove(&near_key(2)));
+        for n in [3, 4] {
+            assert!(matches!(vote(n, 2, &message), Err(Error::AlreadyCompleted)));
+        }
+        for n in [1, 2] {
+            assert!(matches!(vote(n, 1, &message), Err(Error::AlreadyCompleted)));
+        }
+        assert!(!is_pending(1) && !is_pending(2) && !is_executable(2));
+    }
+
+    #[test]
+    fn removing_a_validator_lowers_the_quorum() {
+        register_near(3, None);
+        let message = message_from("NEAR");
+        assert!(vote(1, 1, &message).is_ok());
+        assert!(vote(2, 1, &message).is_ok());
+        assert!(is_pending(1));
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state
+                .chain_validators
+                .get_mut("NEAR")
+                .unwrap()
+                .remove(&validator(3));
+            promote_agreed_messages(&mut state, "NEAR");
+        });
+        assert!(!is_pending(1) && is_executable(1));
+    }
+
+    #[test]
+    fn validators_vote_only_for_their_chain() {
+        register_near(2, None);
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .chain_validators
+                .insert("ETH".to_string(), HashSet::from([validator(9)]))
+        });
+        let message = message_from("NEAR");
+        assert!(matches!(vote(9, 1, &message), Err(Error::NotValidator)));
+        assert!(vote(1, 1, &message).is_ok());
+        assert!(vote(2, 1, &message).is_ok());
+        // the ETH validator doesn't count towards NEAR's quorum
+        assert!(is_executable(1));
+    }
+
+    #[test]
+    fn messages_for_another_chain_or_from_an_unknown_chain_are_rejected() {
+        register_near(1, None);
+        let mut message = message_from("NEAR");
+        message.to_chain = "ETH".to_string();
+        assert!(matches!(vote(1, 1, &message), Err(Error::InvalidToChain)));
+        // validators alone don't make a chain known
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .chain_validators
+                .insert("ETH".to_string(), HashSet::from([validator(1)]))
+        });
+        assert!(matches!(
+            receive(validator(1), 1, message_from("ETH")),
+            Err(Error::UnknownFromChain)
+        ));
+        assert!(!is_pending(1));
+        assert!(STATE.with(|state| state.borrow().latest_message_id.is_empty()));
+    }
+
+    #[test]
+    fn registry_checks_inbound_and_outbound_messages() {
+        register_near(1, None);
+        let mut message = message_from("NEAR");
+        message.sender = "Sender.near".to_string();
+        assert!(matches!(vote(1, 1, &message), Err(Error::InvalidAddress)));
+        let mut content = message_from("NEAR").content;
+        content.data = "[\"NEAR\"]".to_string();
+        STATE.with(|state| {
+            let state = state.borrow();
+            content.contract = "Greeting.near".to_string();
+            assert!(check_outbound(&state, "NEAR", &mut content.clone()).is_err());
+            content.contract = "greeting.near".to_string();
+            assert!(check_outbound(&state, "ETH", &mut content.clone()).is_err());
+            assert!(check_outbound(&state, "NEAR", &mut content.clone()).is_ok());
+        });
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.chain_registry.get_mut("NEAR").unwrap().enabled = false;
+            assert!(check_outbound(&state, "NEAR", &mut content).is_err());
+        });
+        assert!(matches!(
+            vote(1, 1, &message_from("NEAR")),
+            Err(Error::ChainDisabled)
+        ));
+        assert!(!is_pending(1));
+    }
+
+    #[test]
+    fn address_formats() {
+        assert!(AddressFormat::Principal.is_valid("aaaaa-aa"));
+        assert!(!AddressFormat::Principal.is_valid("greeting.near"));
+        assert!(AddressFormat::Evm.is_valid("0x5B38Da6a701c568545dCfcB03FcB875f56beddC4"));
+        assert!(!AddressFormat::Evm.is_valid("5B38Da6a701c568545dCfcB03FcB875f56beddC4"));
+        assert!(!AddressFormat::Evm.is_valid("0x5B38Da6a701c568545dCfcB03FcB875f56beddC"));
+        assert!(AddressFormat::NearAccount.is_valid("app_1.greeting-dev.near"));
+        assert!(!AddressFormat::NearAccount.is_valid("a"));
+        assert!(!AddressFormat::NearAccount.is_valid("greeting..near"));
+        assert!(!AddressFormat::NearAccount.is_valid("Greeting.near"));
+        assert!(AddressFormat::Any.is_valid(""));
+    }
+
+    #[test]
+    fn batches_apply_every_entry_in_order() {
+        register_near(1, None);
+        let entry = |id| ReceivedMessage {
+            id,
+            message: message_from("NEAR"),
+        };
+        let results = receive_batch(validator(1), vec![entry(1), entry(3), entry(2)]);
+        assert!(matches!(
+            results.as_deref(),
+            Ok([Ok(_), Err(Error::IdTooHigh { expected: 2 }), Ok(_)])
+        ));
+        assert!(is_executable(1) && is_executable(2));
+        let oversized = (1..=MAX_RECEIVE_BATCH + 1).map(entry).collect();
+        assert!(matches!(
+            receive_batch(validator(1), oversized),
+            Err(Error::BatchTooLarge {
+                max: MAX_RECEIVE_BATCH
+            })
+        ));
+        assert!(!is_executable(3));
+    }
+
+    #[test]
+    fn conflicting_votes_are_recorded() {
+        register_near(3, Some(Quorum::Count(2)));
+        let message = message_from("NEAR");
+        assert!(vote(1, 1, &message).is_ok());
+        assert!(vote(2, 1, &other_message()).is_ok());
+        STATE.with(|state| {
+            let state = state.borrow();
+            let evidence = &state.conflicts[&near_key(1)];
+            assert_eq!(evidence.id, 1);
+            assert_eq!(evidence.votes.len(), 2);
+            assert!(!evidence.resolved);
+            assert!(state.frozen_message.is_empty());
+        });
+    }
+
+    #[test]
+    fn frozen_conflict_waits_for_a_custodian() {
+        register_near(3, Some(Quorum::Count(2)));
+        STATE.with(|state| state.borrow_mut().freeze_on_conflict = true);
+        let message = message_from("NEAR");
+        assert!(vote(1, 1, &message).is_ok());
+        assert!(vote(2, 1, &other_message()).is_ok());
+        assert!(vote(3, 1, &message).is_ok());
+        assert!(is_pending(1) && !is_executable(1));
+        assert!(STATE.with(|state| state.borrow().frozen_message.contains(&near_key(1))));
+    }
+
+    #[test]
+    fn rejected_votes_return_errors() {
+        register_near(2, None);
+        let message = message_from("NEAR");
+        assert!(matches!(
+            vote(1, 2, &message),
+            Err(Error::IdTooHigh { expected: 1 })
+        ));
+        assert!(!is_pending(2));
+        assert!(vote(1, 1, &message).is_ok());
+        assert!(matches!(vote(1, 1, &message), Err(Error::AlreadyReceived)));
+        assert!(vote(1, 2, &message).is_ok());
+        assert!(matches!(vote(1, 1, &message), Err(Error::DuplicateVote)));
+        assert!(is_pending(1) && is_pending(2));
+    }
+
+    #[test]
+    fn error_callback_is_sent_on_the_first_failure() {
+        register_near(1, None);
+        let mut request = message_from("NEAR");
+        request.session = Session {
+            res_type: SESSION_REQUEST,
+            id: 7,
+            callback: Some("onError".to_string()),
+        };
+        for _ in 0..DEFAULT_MAX_EXECUTION_ATTEMPTS {
+            record_failure(
+                near_key(1),
+                request.clone(),
+                RejectionCode::CanisterReject,
+                "boom".to_string(),
+            );
+        }
+        assert_eq!(
+            STATE.with(|state| state.borrow().sent_message_count.get("NEAR").copied()),
+            Some(1)
+        );
+        let reply = SENT_MESSAGE
+            .with(|sent| sent.borrow().get(&near_key(1)))
+            .unwrap();
+        assert_eq!(reply.session.res_type, SESSION_ERROR);
+        assert_eq!(reply.session.id, 7);
+        assert_eq!(reply.content.contract, "sender.near");
+        assert_eq!(reply.content.action, "onError");
+        assert_eq!(reply.content.data, "[4,\"boom\"]");
+        assert!(matches!(
+            reply.content.encoding,
+            Some(PayloadEncoding::Json)
+        ));
+    }
+
+    #[test]
+    fn near_takes_json_or_raw_payloads() {
+        register_near(1, None);
+        let mut content = message_from("NEAR").content;
+        content.contract = "greeting.near".to_string();
+        content.data = "[\"NEAR\"]".to_string();
+        STATE.with(|state| {
+            let state = state.borrow();
+            assert!(check_outbound(&state, "NEAR", &mut content).is_ok());
+            assert!(matches!(content.encoding, Some(PayloadEncoding::Json)));
+            content.data = "(\"NEAR\")".to_string();
+            content.encoding = Some(PayloadEncoding::CandidText);
+            assert!(check_outbound(&state, "NEAR", &mut content).is_err());
+        });
+    }
+
+    fn failed(chain_name: &str, status: FailedStatus) -> FailedMessage {
+        FailedMessage {
+            message: message_from(chain_name),
+            attempts: 1,
+            reject_code: RejectionCode::CanisterReject,
+            reject_message: String::new(),
+            status,
+        }
+    }
+
+    #[test]
+    fn pages_walk_one_chain_in_id_order() {
+        SENT_MESSAGE.with(|sent| {
+            let mut sent = sent.borrow_mut();
+            for id in 1..=MAX_PAGE_SIZE + 1 {
+                sent.insert(near_key(id), message_from("DFINITY"));
+            }
+            sent.insert(
+                MapKey::MessageId {
+                    chain_name: "ETH".to_string(),
+                    id: 1,
+                },
+                other_message(),
+            );
+        });
+        let page = get_sent_message_page("NEAR".to_string(), 0, 2);
+        let ids: Vec<u64> = page.entries.iter().map(|(id, _)| *id).collect();
+        assert_eq!((ids, page.next_id), (vec![1, 2], Some(3)));
+        let page = get_sent_message_page("NEAR".to_string(), MAX_PAGE_SIZE, 2);
+        let ids: Vec<u64> = page.entries.iter().map(|(id, _)| *id).collect();
+        assert_eq!(
+            (ids, page.next_id),
+            (vec![MAX_PAGE_SIZE, MAX_PAGE_SIZE + 1], None)
+        );
+        // 0 and oversized limits both get the largest page
+        for limit in [0, MAX_PAGE_SIZE + 1] {
+            let page = get_sent_message_page("NEAR".to_string(), 1, limit);
+            assert_eq!(page.entries.len() as u64, MAX_PAGE_SIZE);
+            assert_eq!(page.next_id, Some(MAX_PAGE_SIZE + 1));
+        }
+        let page = get_sent_message_page("ETH".to_string(), 0, 0);
+        assert_eq!((page.entries.len(), page.next_id), (1, None));
+    }
+
+    #[test]
+    fn first_undelivered_skips_done_messages() {
+        let key = |id| MapKey::MessageId {
+            chain_name: "ETH".to_string(),
+            id,
+        };
+        let state = State {
+            latest_message_id: HashMap::from([("ETH".to_string(), 6)]),
+            ..Default::default()
+        };
+        // every message received so far is done
+        assert_eq!(first_undelivered(&state, "ETH", 0), 7);
+        FAILED_MESSAGE.with(|failed_messages| {
+            let mut failed_messages = failed_messages.borrow_mut();
+            failed_messages.insert(key(3), failed("ETH", FailedStatus::DeadLetter));
+            failed_messages.insert(key(5), failed("ETH", FailedStatus::Retryable));
+        });
+        EXECUTABLE_MESSAGE
+            .with(|executable| executable.borrow_mut().insert(key(6), message_from("ETH")));
+        assert_eq!(first_undelivered(&state, "ETH", 0), 5);
+        EXECUTABLE_MESSAGE
+            .with(|executable| executable.borrow_mut().insert(key(4), message_from("ETH")));
+        assert_eq!(first_undelivered(&state, "ETH", 0), 4);
+        // ids at or below the delivered mark are left behind
+        assert_eq!(first_undelivered(&state, "ETH", 4), 5);
+        assert_eq!(first_undelivered(&state, "ETH", 6), 7);
+        assert_eq!(first_undelivered(&state, "ETH", 9), 10);
+        assert_eq!(first_undelivered(&state, "NEAR", 0), 1);
+    }
+
+    #[test]
+    fn delivered_mark_never_moves_back() {
+        register_near(1, None);
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state
+                .chain_registry
+                .get_mut("NEAR")
+                .unwrap()
+                .ordered_delivery = true;
+            state.latest_message_id.insert("NEAR".to_string(), 3);
+            state.delivered_message_id.insert("NEAR".to_string(), 2);
+        });
+        // an entry left behind the mark, e.g. by a vote after message 1 was done
+        PENDING_MESSAGE.with(|pending| {
+            pending.borrow_mut().insert(
+                near_key(1),
+                PendingEntry {
+                    epoch: 0,
+                    validators: vec![validator(1)],
+                    groups: BTreeMap::new(),
+                },
+            )
+        });
+        assert_eq!(expected_in_order("NEAR", 4), Some(3));
+        advance_delivered("NEAR", 3);
+        assert_eq!(
+            STATE.with(|state| state.borrow().delivered_message_id.get("NEAR").copied()),
+            Some(3)
+        );
+        assert_eq!(expected_in_order("NEAR", 4), None);
+    }
+
+    #[test]
+    fn validators_added_after_the_first_vote_dont_count() {
+        register_near(2, None);
+        let message = message_from("NEAR");
+        assert!(vote(1, 1, &message).is_ok());
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .chain_validators
+                .get_mut("NEAR")
+                .unwrap()
+                .insert(validator(3))
+        });
+        assert!(vote(3, 1, &message).is_ok());
+        assert!(is_pending(1));
+        assert!(vote(2, 1, &message).is_ok());
+        assert!(is_executable(1));
+    }
+
+    // messages as they were hashed before content carried its encoding
+    #[derive(Serialize)]
+    struct ContentWithoutEncoding {
+        contract: String,
+        action: String,
+        data: String,
+    }
+
+    #[derive(Serialize)]
+    struct MessageWithoutEncoding {
+        from_chain: String,
+        to_chain: String,
+        sender: String,
+        signer: String,
+        sqos: Sqos,
+        content: ContentWithoutEncoding,
+        session: Session,
+    }
+
+    #[test]
+    fn message_without_encoding_keeps_its_hash() {
+        let message = message_from("NEAR");
+        let legacy = MessageWithoutEncoding {
+            from_chain: message.from_chain.clone(),
+            to_chain: message.to_chain.clone(),
+            sender: message.sender.clone(),
+            signer: message.signer.clone(),
+            sqos: message.sqos.clone(),
+            content: ContentWithoutEncoding {
+                contract: message.content.contract.clone(),
+                action: message.content.action.clone(),
+                data: message.content.data.clone(),
+            },
+            session: message.session.clone(),
+        };
+        let mut data = vec![];
+        let mut serializer = Serializer::new(&mut data);
+        serializer.self_describe().unwrap();
+        legacy.serialize(&mut serializer).unwrap();
+        assert_eq!(message.to_hash(), format!("{:x}", Sha256::digest(data)));
+    }
+
+    #[test]
+    fn empty_stable_memory_has_no_saved_state() {
+        assert!(memory::read_upgrade_bytes().is_none());
+        memory::write_upgrade_bytes(&saved_state(&State::default()));
+        assert!(memory::read_upgrade_bytes().is_some());
+    }
+}
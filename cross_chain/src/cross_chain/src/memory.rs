@@ -0,0 +1,78 @@
+use crate::{ExecutionReceipt, FailedMessage, MapKey, Message, PendingEntry};
+use candid::{Decode, Encode};
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    reader::Reader,
+    storable::Bound,
+    writer::Writer,
+    DefaultMemoryImpl, Memory as _, Storable,
+};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+pub const UPGRADES_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const PENDING_MESSAGE_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const SENT_MESSAGE_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const EXECUTABLE_MESSAGE_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const FAILED_MESSAGE_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const EXECUTED_MESSAGE_MEMORY_ID: MemoryId = MemoryId::new(5);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(id))
+}
+
+pub fn write_upgrade_bytes(bytes: &[u8]) {
+    let mut memory = get(UPGRADES_MEMORY_ID);
+    let mut writer = Writer::new(&mut memory, 0);
+    writer
+        .write(&(bytes.len() as u64).to_le_bytes())
+        .expect("grow upgrades memory failed");
+    writer.write(bytes).expect("grow upgrades memory failed");
+}
+
+// none when nothing was saved, e.g. the previous build had no `pre_upgrade`
+pub fn read_upgrade_bytes() -> Option<Vec<u8>> {
+    let memory = get(UPGRADES_MEMORY_ID);
+    if memory.size() == 0 {
+        return None;
+    }
+    let mut reader = Reader::new(&memory, 0);
+    let mut len = [0u8; 8];
+    reader.read(&mut len).expect("read upgrades memory failed");
+    let mut bytes = vec![0u8; u64::from_le_bytes(len) as usize];
+    reader
+        .read(&mut bytes)
+        .expect("read upgrades memory failed");
+    Some(bytes)
+}
+
+macro_rules! candid_storable {
+    ($($ty:ty),*) => {$(
+        impl Storable for $ty {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                Cow::Owned(Encode!(self).unwrap())
+            }
+
+            fn from_bytes(bytes: Cow<[u8]>) -> Self {
+                Decode!(bytes.as_ref(), Self).unwrap()
+            }
+
+            const BOUND: Bound = Bound::Unbounded;
+        }
+    )*};
+}
+
+candid_storable!(
+    MapKey,
+    Message,
+    PendingEntry,
+    FailedMessage,
+    ExecutionReceipt
+);
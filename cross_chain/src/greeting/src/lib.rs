@@ -6,6 +6,7 @@ use ic_cdk::{
 #[macro_use]
 extern crate serde;
 use ic_cdk_macros::*;
+use near_codec::PayloadEncoding;
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 
@@ -21,6 +22,7 @@ struct Content {
     contract: String,
     action: String,
     data: String,
+    encoding: Option<PayloadEncoding>,
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone)]
@@ -29,6 +31,12 @@ struct Session {
     id: u64,
 }
 
+// arguments of the destination's `receiveGreeting`
+#[derive(Serialize)]
+struct GreetingArgs {
+    greeting: [String; 4],
+}
+
 #[derive(CandidType, Deserialize, Clone)]
 struct DstContract {
     contract_address: String,
@@ -144,14 +152,6 @@ async fn send_greeting(
     content: String,
     date: String,
 ) -> Result<bool, String> {
-    // let greeting_action_data = json!({
-    //     "greeting": [to_chain, title, content, date]
-    // }).to_string();
-    let from_chain = "DFINITY".to_string();
-    let greeting_action_data: String = format!(
-        r#"{{"greeting": ["{}","{}","{}","{}"]}}"#,
-        from_chain, title, content, date
-    );
     let action_name = "receiveGreeting".to_string();
     let destination_contract = STATE.with(|state| {
         let state = state.borrow();
@@ -169,6 +169,15 @@ async fn send_greeting(
         let state = state.borrow();
         state.cross_chain_canister.unwrap()
     });
+    let (from_chain,) = api::call::call::<(), (String,)>(cross_chain_canister, "getLocalChain", ())
+        .await
+        .map_err(|err| {
+            api::print(format!("{:?}", err));
+            "call cross canister failed".to_string()
+        })?;
+    let greeting_action_data = near_codec::to_json(&GreetingArgs {
+        greeting: [from_chain, title, content, date],
+    })?;
 
     let result = api::call::call::<(String, Content, Session), ()>(
         cross_chain_canister,
@@ -178,7 +187,8 @@ async fn send_greeting(
             Content {
                 contract: destination_contract.contract_address.clone(),
                 action: destination_contract.action_name.clone(),
-                data: greeting_action_data,
+                data: greeting_action_data.data,
+                encoding: Some(greeting_action_data.encoding),
             },
             Session { res_type: 0, id: 0 },
         ),
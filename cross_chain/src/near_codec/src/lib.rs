@@ -0,0 +1,75 @@
+// Function call arguments for NEAR contracts, built by locker canisters as
+// the `data` of an outbound cross chain message.
+
+use borsh::BorshSerialize;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+// lockers deriving `BorshSerialize` need `#[borsh(crate = "near_codec::borsh")]`
+pub use borsh;
+
+// the NEAR subset of the cross chain canister's `PayloadEncoding`
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy)]
+pub enum PayloadEncoding {
+    Json,
+    // hex of the Borsh bytes
+    Raw,
+}
+
+pub struct Payload {
+    pub data: String,
+    pub encoding: PayloadEncoding,
+}
+
+// JSON arguments, the default for `near-sdk` contract methods
+pub fn to_json<T: Serialize>(args: &T) -> Result<Payload, String> {
+    Ok(Payload {
+        data: serde_json::to_string(args).map_err(|err| format!("{}", err))?,
+        encoding: PayloadEncoding::Json,
+    })
+}
+
+// Borsh arguments, for methods declared with `#[serializer(borsh)]`
+pub fn to_borsh<T: BorshSerialize>(args: &T) -> Result<Payload, String> {
+    let bytes = borsh::to_vec(args).map_err(|err| format!("{}", err))?;
+    Ok(Payload {
+        data: hex::encode(bytes),
+        encoding: PayloadEncoding::Raw,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, BorshSerialize)]
+    struct Greeting {
+        greeting: String,
+        count: u32,
+    }
+
+    #[test]
+    fn json_escapes_quotes() {
+        let greeting = Greeting {
+            greeting: "say \"hi\"".to_string(),
+            count: 7,
+        };
+        let payload = to_json(&greeting).unwrap();
+        assert!(matches!(payload.encoding, PayloadEncoding::Json));
+        assert_eq!(payload.data, r#"{"greeting":"say \"hi\"","count":7}"#);
+        let decoded: serde_json::Value = serde_json::from_str(&payload.data).unwrap();
+        assert_eq!(decoded["greeting"], "say \"hi\"");
+    }
+
+    #[test]
+    fn borsh_is_hex_encoded() {
+        let greeting = Greeting {
+            greeting: "hi".to_string(),
+            count: 7,
+        };
+        let payload = to_borsh(&greeting).unwrap();
+        assert!(matches!(payload.encoding, PayloadEncoding::Raw));
+        // little endian string length, the bytes, then the count
+        assert_eq!(payload.data, "02000000686907000000");
+    }
+}
@@ -1,4 +1,5 @@
 const idlFactory = ({ IDL }) => {
+  const AbiValue = IDL.Rec();
   const ApiError = IDL.Variant({
     'AlreadyRegisterValidator' : IDL.Null,
     'Unauthorized' : IDL.Null,
@@ -6,6 +7,24 @@ const idlFactory = ({ IDL }) => {
     'ExecuteMessageFailed' : IDL.Null,
     'NotValidator' : IDL.Null,
     'AlreadyRegisterLocker' : IDL.Null,
+    'InvalidQuorum' : IDL.Null,
+    'ConflictNotFound' : IDL.Null,
+    'IdTooHigh' : IDL.Record({ 'expected' : IDL.Nat64 }),
+    'AlreadyReceived' : IDL.Null,
+    'AlreadyCompleted' : IDL.Null,
+    'DuplicateVote' : IDL.Null,
+    'MessageNotExists' : IDL.Null,
+    'MessageExecuting' : IDL.Null,
+    'NotRetryable' : IDL.Null,
+    'InvalidInterface' : IDL.Null,
+    'InvalidToChain' : IDL.Null,
+    'UnknownFromChain' : IDL.Null,
+    'ChainNotRegistered' : IDL.Null,
+    'ChainDisabled' : IDL.Null,
+    'InvalidAddress' : IDL.Null,
+    'InvalidContract' : IDL.Null,
+    'OutOfOrder' : IDL.Record({ 'expected' : IDL.Nat64 }),
+    'BatchTooLarge' : IDL.Record({ 'max' : IDL.Nat64 }),
   });
   const Result = IDL.Variant({ 'Ok' : IDL.Bool, 'Err' : ApiError });
   const MapKey = IDL.Variant({
@@ -15,13 +34,39 @@ const idlFactory = ({ IDL }) => {
     }),
     'MessageId' : IDL.Record({ 'id' : IDL.Nat64, 'chain_name' : IDL.Text }),
   });
+  const PayloadEncoding = IDL.Variant({
+    'CandidText' : IDL.Null,
+    'CandidBinary' : IDL.Null,
+    'Json' : IDL.Null,
+    'Raw' : IDL.Null,
+    'EvmAbi' : IDL.Null,
+  });
   const Content = IDL.Record({
     'action' : IDL.Text,
     'contract' : IDL.Text,
     'data' : IDL.Text,
+    'encoding' : IDL.Opt(PayloadEncoding),
   });
   const Sqos = IDL.Record({ 'reveal' : IDL.Nat8 });
-  const Session = IDL.Record({ 'id' : IDL.Nat64, 'res_type' : IDL.Nat8 });
+  const Session = IDL.Record({
+    'id' : IDL.Nat64,
+    'res_type' : IDL.Nat8,
+    'callback' : IDL.Opt(IDL.Text),
+  });
+  AbiValue.fill(
+    IDL.Variant({
+      'Int' : IDL.Int,
+      'Address' : IDL.Text,
+      'Bool' : IDL.Bool,
+      'Uint' : IDL.Nat,
+      'FixedArray' : IDL.Vec(AbiValue),
+      'Bytes' : IDL.Vec(IDL.Nat8),
+      'Tuple' : IDL.Vec(AbiValue),
+      'String' : IDL.Text,
+      'FixedBytes' : IDL.Vec(IDL.Nat8),
+      'Array' : IDL.Vec(AbiValue),
+    })
+  );
   const Message = IDL.Record({
     'content' : Content,
     'to_chain' : IDL.Text,
@@ -31,6 +76,11 @@ const idlFactory = ({ IDL }) => {
     'session' : Session,
     'signer' : IDL.Text,
   });
+  const SentMessage = IDL.Record({
+    'id' : IDL.Nat64,
+    'hash' : IDL.Text,
+    'message' : Message,
+  });
   const PendingMessage = IDL.Record({
     'message' : Message,
     'validators' : IDL.Vec(IDL.Principal),
@@ -39,6 +89,11 @@ const idlFactory = ({ IDL }) => {
     'clearRecivedMessage' : IDL.Func([IDL.Vec(IDL.Text)], [Result], []),
     'clearSentMessage' : IDL.Func([IDL.Vec(IDL.Text)], [Result], []),
     'executeMessage' : IDL.Func([IDL.Text, IDL.Nat64], [Result], []),
+    'executeMessages' : IDL.Func(
+        [IDL.Text, IDL.Nat64],
+        [IDL.Vec(IDL.Tuple(IDL.Nat64, Result))],
+        [],
+      ),
     'getCustodians' : IDL.Func([], [IDL.Vec(IDL.Principal)], ['query']),
     'getExecutableMessage' : IDL.Func(
         [],
@@ -51,6 +106,7 @@ const idlFactory = ({ IDL }) => {
         ['query'],
       ),
     'getLatestMessageId' : IDL.Func([IDL.Text], [IDL.Nat64], ['query']),
+    'getLocalChain' : IDL.Func([], [IDL.Text], ['query']),
     'getLockers' : IDL.Func([], [IDL.Vec(IDL.Principal)], ['query']),
     'getMsgPortingTask' : IDL.Func(
         [IDL.Text, IDL.Principal],
@@ -76,16 +132,31 @@ const idlFactory = ({ IDL }) => {
         [Message],
         ['query'],
       ),
+    'getSentMessagesFrom' : IDL.Func(
+        [IDL.Text, IDL.Nat64, IDL.Nat64],
+        [IDL.Vec(SentMessage)],
+        ['query'],
+      ),
     'getSentMessageCount' : IDL.Func([IDL.Text], [IDL.Nat64], ['query']),
-    'getValidators' : IDL.Func([], [IDL.Vec(IDL.Principal)], ['query']),
+    'getValidators' : IDL.Func([IDL.Text], [IDL.Vec(IDL.Principal)], ['query']),
     'receiveMessage' : IDL.Func([IDL.Nat64, Message], [Result], []),
+    'receiveMessages' : IDL.Func(
+        [IDL.Vec(IDL.Record({ 'id' : IDL.Nat64, 'message' : Message }))],
+        [IDL.Variant({ 'Ok' : IDL.Vec(Result), 'Err' : ApiError })],
+        [],
+      ),
     'registerLocker' : IDL.Func([IDL.Principal], [Result], []),
-    'registerValidator' : IDL.Func([IDL.Principal], [Result], []),
+    'registerValidator' : IDL.Func([IDL.Text, IDL.Principal], [Result], []),
+    'sendAbiMessage' : IDL.Func(
+        [IDL.Text, IDL.Text, IDL.Text, IDL.Vec(AbiValue), Session],
+        [],
+        [],
+      ),
     'sendMessage' : IDL.Func([IDL.Text, Content, Session], [], []),
-    'unRegisterValidator' : IDL.Func([IDL.Principal], [Result], []),
+    'unRegisterValidator' : IDL.Func([IDL.Text, IDL.Principal], [Result], []),
   });
 };
-const init = ({ IDL }) => { return []; };
+const init = ({ IDL }) => { return [IDL.Text]; };
 
 module.exports = {
   idlFactory
@@ -70,6 +70,11 @@ class DfinityHandler {
     return this.actor.getSentMessageById(toChain, id);
   }
 
+  // query up to max consecutive sent messages starting at fromId, 0 for the canister's page size
+  async getSentMessagesFrom(toChain, fromId, max) {
+    return this.actor.getSentMessagesFrom(toChain, fromId, max);
+  }
+
   // push message to Near
   async pushMessage(id, message) {
     // const message = {
@@ -82,7 +87,18 @@ class DfinityHandler {
     // };
     // console.log("Push message: ", message);
     console.log(message);
-    await this.actor.receiveMessage(id, message);
+    const result = await this.actor.receiveMessage(id, message);
+    if ("Err" in result) {
+      const err = result.Err;
+      if ("AlreadyReceived" in err || "AlreadyCompleted" in err || "DuplicateVote" in err) {
+        // another validator got there first, nothing left to do for this id
+        console.log("InternetComputer message " + id + " already ported: " + Object.keys(err)[0]);
+      } else if ("IdTooHigh" in err) {
+        console.log("InternetComputer message " + id + " is ahead, expected " + err.IdTooHigh.expected);
+      } else {
+        throw new Error("receiveMessage failed: " + Object.keys(err)[0]);
+      }
+    }
   }
 
   //
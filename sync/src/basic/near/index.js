@@ -124,6 +124,12 @@ class NearHandler {
         id: session[1],
       }
     }
+    if (content[3] !== undefined) {
+      args.content.encoding = content[3]; // payload encoding, Json or Raw
+    }
+    if (session[2] !== undefined) {
+      args.session.callback = session[2]; // action taking the response
+    }
     console.log('Push message', args);
     await this.pushTransaction("receive_message", args);
   }
@@ -16,6 +16,8 @@ async function sendMessage(fromChain, toChain) {
     let message = await fromHandler.getSentMessageById(toChain, Number(nextMessageId));
     message.sqos = { reveal: 1 };
     message.session = getSession(message.session);
+    // NEAR payloads are Candid text unless stated otherwise
+    message.content.encoding = message.content.encoding ? [message.content.encoding] : [];
     await toHandler.pushMessage(nextMessageId, message);
   }
 }
@@ -24,10 +26,12 @@ function getSession(session) {
   if (!session) {
     session = {
       res_type: 0,
-      id: 0
+      id: 0,
+      callback: []
     }
   } else {
     session.id = session.id ? session.id : 0;
+    session.callback = session.callback ? [session.callback] : [];
   }
   return session;
 }
@@ -11,17 +11,24 @@ async function sendMessage(fromChain, toChain) {
   // query Near next receive message Id
   let nextMessageId = await toHandler.queryNextMessageId(fromChain);
 
-  // push messge
+  // push the backlog in id order, a failed push stops the batch
   if (nextMessageId <= dfinityMessageCount) {
-    let id = nextMessageId;
-    let message = await fromHandler.getSentMessageById(toChain, id);
-    // TODO check message is irreversible
-    let m = [id, fromChain, message.to_chain, message.sender, message.signer, { reveal: true },
-      [message.content.contract, message.content.action, message.content.data], [message.session.res_type, Number(message.session.id)]];
-    await toHandler.pushMessage(m);
+    let sentMessages = await fromHandler.getSentMessagesFrom(toChain, nextMessageId, 0);
+    for (const { id, message } of sentMessages) {
+      // TODO check message is irreversible
+      let m = [Number(id), fromChain, message.to_chain, message.sender, message.signer, { reveal: true },
+        [message.content.contract, message.content.action, message.content.data, variantName(message.content.encoding)],
+        [message.session.res_type, Number(message.session.id), message.session.callback[0]]];
+      await toHandler.pushMessage(m);
+    }
   }
 }
 
+// opt variants come back as `[]` or `[{ Json: null }]`, NEAR takes the name
+function variantName(opt) {
+  return opt.length ? Object.keys(opt[0])[0] : undefined;
+}
+
 module.exports = {
   sendMessage: sendMessage,
 }
\ No newline at end of file